@@ -0,0 +1,17 @@
+#!/bin/bash
+
+# Render the bundled layouts with the fixed sample readings of --render-to.
+# Run from the repository root, then review the changes with `git diff docs/snapshots`.
+set -e
+
+cargo build --quiet
+BIN=target/debug/rustberry-poe-monitor
+OUT=docs/snapshots
+
+for config in etc_rustberry-poe-monitor/landscape.json etc_rustberry-poe-monitor/portrait.json; do
+    name=$(basename "$config" .json)
+    $BIN --quiet --config "$config" --render-to "$OUT/$name.txt"
+done
+
+# The built-in layout, used when no config file can be loaded
+$BIN --quiet --config /nonexistent --render-to "$OUT/default.txt" 2>/dev/null
@@ -0,0 +1,60 @@
+use anyhow::{anyhow, Result};
+use linux_embedded_hal::i2cdev::core::I2CDevice;
+use linux_embedded_hal::i2cdev::linux::LinuxI2CDevice;
+use log::{info, trace};
+
+use crate::display_types::Orientation;
+
+const I2C_BUS_PATH: &str = "/dev/i2c-1";
+// Bus address of an ADXL345 with its SDO/ALT ADDRESS pin low
+const ADXL345_ADDRESS: u16 = 0x53;
+const REG_DEVID: u8 = 0x00;
+const ADXL345_DEVID: u8 = 0xE5;
+const REG_POWER_CTL: u8 = 0x2D;
+const POWER_CTL_MEASURE: u8 = 0x08;
+// X, Y and Z acceleration, each as a little-endian i16
+const REG_DATAX0: u8 = 0x32;
+// Gravity has to pull this much harder along one axis than along the other before it
+// counts, so a board lying flat or held at 45° doesn't switch back and forth
+const DOMINANCE: f32 = 1.5;
+
+// ADXL345 accelerometer mounted with its X axis along the panel's long side and its Y axis
+// pointing up while the panel is in its normal landscape position
+pub struct Accelerometer {
+    device: LinuxI2CDevice,
+}
+
+impl Accelerometer {
+    pub fn new() -> Result<Self> {
+        let mut device = LinuxI2CDevice::new(I2C_BUS_PATH, ADXL345_ADDRESS)?;
+        let id = device.smbus_read_byte_data(REG_DEVID)?;
+        if id != ADXL345_DEVID {
+            return Err(anyhow!("No ADXL345 at address 0x{:02x} (device id 0x{:02x})", ADXL345_ADDRESS, id));
+        }
+        device.smbus_write_byte_data(REG_POWER_CTL, POWER_CTL_MEASURE)?;
+        info!("ADXL345 accelerometer initialized");
+        Ok(Accelerometer { device })
+    }
+
+    // Orientation of the panel from the direction of gravity. None while neither axis
+    // clearly points up or down, e.g. while the device lies flat.
+    pub fn orientation(&mut self) -> Result<Option<Orientation>> {
+        let data = self.device.smbus_read_i2c_block_data(REG_DATAX0, 6)?;
+        if data.len() < 4 {
+            return Err(anyhow!("Short read from the accelerometer: {} bytes", data.len()));
+        }
+        // At rest the axis pointing up reads +1g
+        let x = i16::from_le_bytes([data[0], data[1]]) as f32;
+        let y = i16::from_le_bytes([data[2], data[3]]) as f32;
+        trace!("Acceleration x={} y={}", x, y);
+
+        let orientation = if y.abs() > x.abs() * DOMINANCE {
+            Some(if y > 0.0 { Orientation::Landscape } else { Orientation::LandscapeFlipped })
+        } else if x.abs() > y.abs() * DOMINANCE {
+            Some(if x > 0.0 { Orientation::Portrait } else { Orientation::PortraitFlipped })
+        } else {
+            None
+        };
+        Ok(orientation)
+    }
+}
@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+use crate::format::{format_bytes, format_number, NumberFormat};
+use crate::metrics::{read_value_file, InterfaceIp, ProcessUsage};
+
+use crate::commands;
+use crate::display::DisplayValues;
+
+// Live readings a component value can name, e.g. "cpu_temp" or "{cpu_temp}" in a template
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Binding {
+    Interface,
+    InterfacePhys,
+    InterfaceVlan,
+    IpOctet(usize),
+    CpuUsage,
+    CpuTemp,
+    RamUsage,
+    DiskUsage,
+    RamUsedBytes,
+    RamTotalBytes,
+    DiskUsedBytes,
+    DiskTotalBytes,
+    Throttled,
+    Poe,
+    FanState,
+    Model,
+    HatProduct,
+    HatVendor,
+    NetRxErrors,
+    NetTxErrors,
+    NetDrops,
+    ProcCpu,
+    ProcMem,
+    File,
+    FileScaled,
+    Cmd,
+}
+
+impl Binding {
+    pub const ALL: [Binding; 29] = [
+        Binding::Interface,
+        Binding::InterfacePhys,
+        Binding::InterfaceVlan,
+        Binding::IpOctet(0),
+        Binding::IpOctet(1),
+        Binding::IpOctet(2),
+        Binding::IpOctet(3),
+        Binding::CpuUsage,
+        Binding::CpuTemp,
+        Binding::RamUsage,
+        Binding::DiskUsage,
+        Binding::RamUsedBytes,
+        Binding::RamTotalBytes,
+        Binding::DiskUsedBytes,
+        Binding::DiskTotalBytes,
+        Binding::Throttled,
+        Binding::Poe,
+        Binding::FanState,
+        Binding::Model,
+        Binding::HatProduct,
+        Binding::HatVendor,
+        Binding::NetRxErrors,
+        Binding::NetTxErrors,
+        Binding::NetDrops,
+        Binding::ProcCpu,
+        Binding::ProcMem,
+        Binding::File,
+        Binding::FileScaled,
+        Binding::Cmd,
+    ];
+
+    // Name used in the layout
+    pub fn name(&self) -> &'static str {
+        match self {
+            Binding::Interface => "ip_info.0",
+            Binding::InterfacePhys => "interface_phys",
+            Binding::InterfaceVlan => "interface_numvlan",
+            Binding::IpOctet(0) => "ip_octets(0)",
+            Binding::IpOctet(1) => "ip_octets(1)",
+            Binding::IpOctet(2) => "ip_octets(2)",
+            Binding::IpOctet(_) => "ip_octets(3)",
+            Binding::CpuUsage => "cpu_usage",
+            Binding::CpuTemp => "cpu_temp",
+            Binding::RamUsage => "ram_usage",
+            Binding::DiskUsage => "disk_usage",
+            Binding::RamUsedBytes => "ram_used_bytes",
+            Binding::RamTotalBytes => "ram_total_bytes",
+            Binding::DiskUsedBytes => "disk_used_bytes",
+            Binding::DiskTotalBytes => "disk_total_bytes",
+            Binding::Throttled => "throttled",
+            Binding::Poe => "poe",
+            Binding::FanState => "fan_state",
+            Binding::Model => "model",
+            Binding::HatProduct => "hat_product",
+            Binding::HatVendor => "hat_vendor",
+            Binding::NetRxErrors => "net_rx_errors",
+            Binding::NetTxErrors => "net_tx_errors",
+            Binding::NetDrops => "net_drops",
+            Binding::ProcCpu => "proc_cpu(<name>)",
+            Binding::ProcMem => "proc_mem(<name>)",
+            Binding::File => "file(<path>)",
+            Binding::FileScaled => "file_scaled(<path>,<divisor>)",
+            Binding::Cmd => "cmd(<command>)",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Binding::Interface => "Interface name, e.g. eth0.100",
+            Binding::InterfacePhys => "Physical interface, e.g. eth0",
+            Binding::InterfaceVlan => "VLAN number of the interface, empty without a VLAN",
+            Binding::IpOctet(0) => "First octet of the IP address",
+            Binding::IpOctet(1) => "Second octet of the IP address",
+            Binding::IpOctet(2) => "Third octet of the IP address",
+            Binding::IpOctet(_) => "Last octet of the IP address",
+            Binding::CpuUsage => "CPU usage in percent",
+            Binding::CpuTemp => "CPU temperature in the --temp-unit",
+            Binding::RamUsage => "Used RAM in percent",
+            Binding::DiskUsage => "Used space on the root filesystem in percent",
+            Binding::RamUsedBytes => "Used RAM, e.g. 1.5GB",
+            Binding::RamTotalBytes => "Total RAM, e.g. 4.0GB",
+            Binding::DiskUsedBytes => "Used disk space, e.g. 19.5GB",
+            Binding::DiskTotalBytes => "Total disk space, e.g. 32.0GB",
+            Binding::Throttled => "OK, UV (under-voltage) or THR (throttled)",
+            Binding::Poe => "PoE or USB power, from --poe-status-path",
+            Binding::FanState => "ON/OFF, or the layout's fan_state_labels",
+            Binding::Model => "Board model, e.g. Raspberry Pi 4 Model B Rev 1.4",
+            Binding::HatProduct => "Product name from the HAT's EEPROM",
+            Binding::HatVendor => "Vendor from the HAT's EEPROM",
+            Binding::NetRxErrors => "Receive errors on eth0, in total or per frame (net_counters)",
+            Binding::NetTxErrors => "Transmit errors on eth0, in total or per frame (net_counters)",
+            Binding::NetDrops => "Packets dropped on eth0, in total or per frame (net_counters)",
+            Binding::ProcCpu => "CPU usage of the processes with this name or PID, in percent of one core",
+            Binding::ProcMem => "Memory used by the processes with this name or PID, e.g. 25.3MB",
+            Binding::File => "Contents of a text file, e.g. a sysfs attribute",
+            Binding::FileScaled => "Number in a text file divided by the divisor, e.g. 1000 for millidegrees",
+            Binding::Cmd => "First line printed by a shell command, run at most once a minute (needs --allow-commands)",
+        }
+    }
+
+    // Current value for the given readings and interface; None if unavailable
+    fn value(&self, values: &DisplayValues, fan_state: &str, ip_info: Option<&InterfaceIp>) -> Option<BindingValue> {
+        let text = |text: String| Some(BindingValue::Text(text));
+        match self {
+            Binding::Interface => text(ip_info?.0.clone()),
+            Binding::InterfacePhys => text(split_interface(&ip_info?.0).0),
+            Binding::InterfaceVlan => text(split_interface(&ip_info?.0).1),
+            Binding::IpOctet(octet) => text(ip_info?.2[*octet].to_string()),
+            Binding::CpuUsage => Some(BindingValue::Number(values.cpu_usage as f64)),
+            Binding::CpuTemp => values.cpu_temp.map(BindingValue::Number),
+            Binding::RamUsage => values.ram_usage.map(BindingValue::Number),
+            Binding::DiskUsage => values.disk_usage.map(BindingValue::Number),
+            Binding::RamUsedBytes => Some(BindingValue::Bytes(values.ram_bytes?.used)),
+            Binding::RamTotalBytes => Some(BindingValue::Bytes(values.ram_bytes?.total)),
+            Binding::DiskUsedBytes => Some(BindingValue::Bytes(values.disk_bytes?.used)),
+            Binding::DiskTotalBytes => Some(BindingValue::Bytes(values.disk_bytes?.total)),
+            Binding::Throttled => text(values.throttled?.to_string()),
+            Binding::Poe => text(values.poe?.to_string()),
+            Binding::FanState => text(fan_state.to_string()),
+            Binding::Model => text(values.model?.to_string()),
+            Binding::HatProduct => text(values.hat_product?.to_string()),
+            Binding::HatVendor => text(values.hat_vendor?.to_string()),
+            Binding::NetRxErrors => Some(BindingValue::Count(values.net_errors?.rx_errors)),
+            Binding::NetTxErrors => Some(BindingValue::Count(values.net_errors?.tx_errors)),
+            Binding::NetDrops => Some(BindingValue::Count(values.net_errors?.drops)),
+            // Take a process name, PID, path or command, so they are looked up by Bindings::get
+            Binding::ProcCpu | Binding::ProcMem | Binding::File | Binding::FileScaled | Binding::Cmd => None,
+        }
+    }
+
+    // Whether this shows details of the current interface
+    pub fn is_interface(&self) -> bool {
+        matches!(self, Binding::Interface | Binding::InterfacePhys | Binding::InterfaceVlan | Binding::IpOctet(_))
+    }
+}
+
+// Numbers are kept as such so each component can format them with its own precision
+enum BindingValue {
+    Number(f64),
+    Bytes(u64),
+    // Shown without decimals
+    Count(u64),
+    Text(String),
+}
+
+impl BindingValue {
+    // Numeric readings get `precision` decimals (default 1); numbers and sizes use the
+    // layout's separators
+    fn format(&self, precision: Option<usize>, number_format: &NumberFormat) -> String {
+        match self {
+            BindingValue::Number(number) => number_format.localize(&format_number(*number, precision.unwrap_or(1))),
+            BindingValue::Bytes(bytes) => number_format.localize(&format_bytes(*bytes)),
+            BindingValue::Count(count) => number_format.localize(&count.to_string()),
+            BindingValue::Text(text) => text.clone(),
+        }
+    }
+}
+
+// Values of every binding for one frame and interface
+pub struct Bindings<'a> {
+    values: HashMap<&'static str, Option<BindingValue>>,
+    processes: &'a [ProcessUsage],
+    number_format: &'a NumberFormat,
+}
+
+impl<'a> Bindings<'a> {
+    pub fn new(values: &DisplayValues<'a>, fan_state: &str, ip_info: Option<&InterfaceIp>, number_format: &'a NumberFormat) -> Self {
+        let processes = values.processes;
+        let values = Binding::ALL.iter()
+            .map(|binding| (binding.name(), binding.value(values, fan_state, ip_info)))
+            .collect();
+        Bindings { values, processes, number_format }
+    }
+
+    // None if `name` isn't a binding, Some(None) if it is but the reading is unavailable
+    pub fn get(&self, name: &str, precision: Option<usize>) -> Option<Option<String>> {
+        let value = match self.values.get(name) {
+            Some(value) => value.as_ref().map(|value| value.format(precision, self.number_format)),
+            None => self.process_value(name).or_else(|| file_value(name)).or_else(|| command_value(name))?.map(|value| value.format(precision, self.number_format)),
+        };
+        Some(value)
+    }
+
+    // proc_cpu(<name or pid>) or proc_mem(<name or pid>), summed over all matching
+    // processes. Unavailable if nothing matches.
+    fn process_value(&self, name: &str) -> Option<Option<BindingValue>> {
+        let (function, query) = name.strip_suffix(')')?.split_once('(')?;
+        let matching: Vec<&ProcessUsage> = self.processes.iter().filter(|process| process.matches(query)).collect();
+        let value = match function {
+            "proc_cpu" => BindingValue::Number(matching.iter().map(|process| process.cpu_usage as f64).sum()),
+            "proc_mem" => BindingValue::Bytes(matching.iter().map(|process| process.memory).sum()),
+            _ => return None,
+        };
+        Some((!matching.is_empty()).then_some(value))
+    }
+}
+
+// file(<path>) or file_scaled(<path>,<divisor>). Files are read on every frame, so they
+// always show the current value. Unavailable if the file can't be read or, for
+// file_scaled, doesn't hold a number.
+fn file_value(name: &str) -> Option<Option<BindingValue>> {
+    let (function, argument) = name.strip_suffix(')')?.split_once('(')?;
+    let value = match function {
+        "file" => read_value_file(argument.trim()).map(BindingValue::Text),
+        "file_scaled" => {
+            let (path, divisor) = argument.rsplit_once(',')?;
+            divisor.trim().parse::<f64>().ok()
+                .filter(|divisor| *divisor != 0.0)
+                .zip(read_value_file(path.trim()).and_then(|text| text.parse::<f64>().ok()))
+                .map(|(divisor, value)| BindingValue::Number(value / divisor))
+        },
+        _ => return None,
+    };
+    Some(value)
+}
+
+// cmd(<command>), the last output of a shell command
+fn command_value(name: &str) -> Option<Option<BindingValue>> {
+    let command = name.strip_prefix("cmd(")?.strip_suffix(')')?;
+    Some(commands::output(command.trim()).map(BindingValue::Text))
+}
+
+// Split a VLAN interface name into its physical interface and VLAN id,
+// e.g. "eth0.100" -> ("eth0", "100"). Names without a VLAN part return an empty id.
+fn split_interface(interface: &str) -> (String, String) {
+    match interface.split_once('.') {
+        Some((phys, vlan)) if !phys.is_empty() && !vlan.is_empty() => (phys.to_string(), vlan.to_string()),
+        _ => (interface.to_string(), String::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::ByteUsage;
+
+    fn values<'a>(processes: &'a [ProcessUsage]) -> DisplayValues<'a> {
+        DisplayValues {
+            interfaces: &[],
+            cpu_usage: 7.25,
+            cpu_temp: Some(48.25),
+            ram_usage: Some(37.0),
+            disk_usage: None,
+            ram_bytes: Some(ByteUsage { used: 1_500_000_000, total: 4_000_000_000 }),
+            disk_bytes: None,
+            processes,
+            throttled: Some("UV"),
+            poe: Some("PoE"),
+            fan_running: true,
+            model: None,
+            hat_product: None,
+            hat_vendor: None,
+            net_errors: None,
+        }
+    }
+
+    fn process(pid: u32, name: &str, cpu_usage: f32, memory: u64) -> ProcessUsage {
+        ProcessUsage { pid, name: name.to_string(), cpu_usage, memory }
+    }
+
+    #[test]
+    fn split_interface_separates_the_vlan() {
+        assert_eq!(split_interface("eth0.1"), ("eth0".to_string(), "1".to_string()));
+        assert_eq!(split_interface("eth0.100"), ("eth0".to_string(), "100".to_string()));
+        assert_eq!(split_interface("eth0.4094"), ("eth0".to_string(), "4094".to_string()));
+        assert_eq!(split_interface("wlan0.20"), ("wlan0".to_string(), "20".to_string()));
+    }
+
+    #[test]
+    fn split_interface_keeps_names_without_a_vlan() {
+        for interface in ["eth0", "wlan0", "enp1s0", "br-lan"] {
+            assert_eq!(split_interface(interface), (interface.to_string(), String::new()));
+        }
+        // A dot without anything on one side isn't a VLAN
+        assert_eq!(split_interface("eth0."), ("eth0.".to_string(), String::new()));
+        assert_eq!(split_interface(".100"), (".100".to_string(), String::new()));
+    }
+
+    #[test]
+    fn resolves_readings_by_name() {
+        let number_format = NumberFormat::default();
+        let ip_info = ("eth0.100".to_string(), "192.168.1.42".to_string(), [192, 168, 1, 42]);
+        let bindings = Bindings::new(&values(&[]), "ON", Some(&ip_info), &number_format);
+        let get = |name: &str| bindings.get(name, None);
+
+        assert_eq!(get("cpu_usage"), Some(Some("7.3".to_string())));
+        assert_eq!(get("cpu_temp"), Some(Some("48.3".to_string())));
+        assert_eq!(get("ram_used_bytes"), Some(Some("1.5GB".to_string())));
+        assert_eq!(get("ram_total_bytes"), Some(Some("4.0GB".to_string())));
+        assert_eq!(get("throttled"), Some(Some("UV".to_string())));
+        assert_eq!(get("fan_state"), Some(Some("ON".to_string())));
+        assert_eq!(get("ip_info.0"), Some(Some("eth0.100".to_string())));
+        assert_eq!(get("interface_phys"), Some(Some("eth0".to_string())));
+        assert_eq!(get("interface_numvlan"), Some(Some("100".to_string())));
+        assert_eq!(get("ip_octets(0)"), Some(Some("192".to_string())));
+        assert_eq!(get("ip_octets(3)"), Some(Some("42".to_string())));
+    }
+
+    #[test]
+    fn unavailable_readings_differ_from_unknown_names() {
+        let number_format = NumberFormat::default();
+        let bindings = Bindings::new(&values(&[]), "OFF", None, &number_format);
+
+        // Known, but there is no reading or interface
+        assert_eq!(bindings.get("disk_usage", None), Some(None));
+        assert_eq!(bindings.get("model", None), Some(None));
+        assert_eq!(bindings.get("ip_octets(0)", None), Some(None));
+        // Not a binding, drawn as literal text
+        assert_eq!(bindings.get("Hello", None), None);
+        assert_eq!(bindings.get("ip_octets(4)", None), None);
+        assert_eq!(bindings.get("proc_swap(sshd)", None), None);
+    }
+
+    #[test]
+    fn process_readings_are_summed_over_matches() {
+        let number_format = NumberFormat::default();
+        let processes = [process(100, "nginx", 2.5, 10_000_000), process(101, "nginx", 1.0, 5_000_000), process(200, "sshd", 0.5, 3_000_000)];
+        let bindings = Bindings::new(&values(&processes), "OFF", None, &number_format);
+
+        assert_eq!(bindings.get("proc_cpu(nginx)", None), Some(Some("3.5".to_string())));
+        assert_eq!(bindings.get("proc_mem(nginx)", None), Some(Some("15.0MB".to_string())));
+        assert_eq!(bindings.get("proc_cpu(200)", Some(0)), Some(Some("1".to_string())));
+        assert_eq!(bindings.get("proc_cpu(apache2)", None), Some(None));
+    }
+
+    #[test]
+    fn byte_readings_switch_units_at_1000() {
+        let number_format = NumberFormat::default();
+        let cases = [(999, "999B"), (1000, "1.0KB"), (999_949_999, "999.9MB"), (999_950_000, "1.0GB"), (32_000_000_000, "32.0GB")];
+        for (bytes, expected) in cases {
+            let readings = DisplayValues {
+                ram_bytes: Some(ByteUsage { used: bytes, total: 4_000_000_000 }),
+                disk_bytes: Some(ByteUsage { used: 0, total: bytes }),
+                ..values(&[])
+            };
+            let bindings = Bindings::new(&readings, "OFF", None, &number_format);
+            assert_eq!(bindings.get("ram_used_bytes", None), Some(Some(expected.to_string())));
+            assert_eq!(bindings.get("disk_total_bytes", None), Some(Some(expected.to_string())));
+            assert_eq!(bindings.get("disk_used_bytes", None), Some(Some("0B".to_string())));
+        }
+    }
+}
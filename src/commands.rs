@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::process::{Command, Stdio};
+use std::sync::{mpsc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+use log::{debug, warn};
+
+// A command is run again at most this often; until then its last output is shown
+const COMMAND_INTERVAL: Duration = Duration::from_secs(60);
+// Commands still running after this are killed
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// Only the start of the output is kept
+const MAX_OUTPUT_SIZE: u64 = 4096;
+
+// Set by --allow-commands. cmd(..) bindings stay unavailable without it, as they run
+// whatever the layout file says.
+static ALLOWED: AtomicBool = AtomicBool::new(false);
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Default)]
+struct CachedOutput {
+    output: Option<String>,
+    // When the command was last started; None if it never was
+    started: Option<Instant>,
+    running: bool,
+}
+
+lazy_static! {
+    static ref OUTPUTS: Mutex<HashMap<String, CachedOutput>> = Mutex::new(HashMap::new());
+}
+
+pub fn allow() {
+    ALLOWED.store(true, Ordering::Relaxed);
+}
+
+// Last output of a shell command for the cmd(<command>) binding. Commands run on a
+// background thread so a slow one can't hold up the display; None until the first run
+// finished, or if it failed.
+pub fn output(command: &str) -> Option<String> {
+    if !ALLOWED.load(Ordering::Relaxed) {
+        if !WARNED.swap(true, Ordering::Relaxed) {
+            warn!("The layout runs shell commands, which are disabled without --allow-commands");
+        }
+        return None;
+    }
+
+    let mut outputs = OUTPUTS.lock().unwrap();
+    let cached = outputs.entry(command.to_string()).or_default();
+    if !cached.running && cached.started.is_none_or(|started| started.elapsed() >= COMMAND_INTERVAL) {
+        cached.running = true;
+        cached.started = Some(Instant::now());
+        let command = command.to_string();
+        thread::spawn(move || {
+            let output = run(&command);
+            let mut outputs = OUTPUTS.lock().unwrap();
+            if let Some(cached) = outputs.get_mut(&command) {
+                cached.output = output;
+                cached.running = false;
+            }
+        });
+    }
+    cached.output.clone()
+}
+
+// First non-empty line of the command's stdout, trimmed. None if it fails, prints nothing
+// or times out.
+fn run(command: &str) -> Option<String> {
+    debug!("Running command: {}", command);
+    let mut child = match Command::new("sh").arg("-c").arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("Failed to run {:?}: {}", command, e);
+            return None;
+        }
+    };
+
+    // Read stdout while the command runs, so one printing more than the pipe holds isn't
+    // stuck writing to it. Only the start is kept, the rest is read and dropped.
+    let mut stdout = child.stdout.take()?;
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut start = Vec::new();
+        let result = (&mut stdout).take(MAX_OUTPUT_SIZE).read_to_end(&mut start)
+            .and_then(|_| io::copy(&mut stdout, &mut io::sink()));
+        let _ = sender.send(result.map(|_| start));
+    });
+
+    let deadline = Instant::now() + COMMAND_TIMEOUT;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() < deadline => thread::sleep(POLL_INTERVAL),
+            Ok(None) => {
+                warn!("Command {:?} timed out after {:?}", command, COMMAND_TIMEOUT);
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            },
+            Err(e) => {
+                warn!("Failed to wait for {:?}: {}", command, e);
+                return None;
+            }
+        }
+    };
+    if !status.success() {
+        debug!("Command {:?} failed: {}", command, status);
+        return None;
+    }
+
+    // Processes started in the background may keep stdout open after the command exits
+    let stdout = match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Ok(Ok(stdout)) => stdout,
+        Ok(Err(e)) => {
+            warn!("Failed to read the output of {:?}: {}", command, e);
+            return None;
+        },
+        Err(_) => {
+            warn!("Output of {:?} was not closed within {:?}", command, COMMAND_TIMEOUT);
+            return None;
+        }
+    };
+    // The kept start may end inside a character
+    String::from_utf8_lossy(&stdout).lines().map(str::trim).find(|line| !line.is_empty()).map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_line_of_the_output() {
+        assert_eq!(run("echo; echo '  up 3 days  '; echo later").as_deref(), Some("up 3 days"));
+        assert_eq!(run("true"), None);
+        assert_eq!(run("echo failed; false"), None);
+    }
+
+    #[test]
+    fn long_output_does_not_block_the_command() {
+        // Far more than a pipe holds; only the start is kept
+        let output = run("head -c 200000 /dev/zero | tr '\\0' x; echo; echo done").unwrap();
+        assert_eq!(output, "x".repeat(MAX_OUTPUT_SIZE as usize));
+    }
+}
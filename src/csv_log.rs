@@ -0,0 +1,59 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use log::info;
+
+const COLUMNS: [&str; 8] = ["timestamp", "cpu_temp", "cpu_usage", "ram_usage", "disk_usage", "fan", "interface", "ip"];
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+// Appends one row of metrics per interval to a CSV file
+pub struct CsvLogger {
+    writer: BufWriter<File>,
+    interval: Duration,
+    last_write: Option<Instant>,
+    last_flush: Instant,
+}
+
+impl CsvLogger {
+    pub fn new(path: &str, interval: Duration) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let is_new = file.metadata()?.len() == 0;
+        let mut writer = BufWriter::new(file);
+        if is_new {
+            writeln!(writer, "{}", COLUMNS.join(","))?;
+            writer.flush()?;
+        }
+
+        info!("Logging metrics to {} every {:?}", path, interval);
+        Ok(CsvLogger { writer, interval, last_write: None, last_flush: Instant::now() })
+    }
+
+    // Write a row (values in COLUMNS order, without the timestamp) if the interval has elapsed
+    pub fn log(&mut self, values: &[&str; 7]) -> std::io::Result<()> {
+        if self.last_write.is_some_and(|t| t.elapsed() < self.interval) {
+            return Ok(());
+        }
+        self.last_write = Some(Instant::now());
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        write!(self.writer, "{}", timestamp)?;
+        for value in values {
+            write!(self.writer, ",{}", escape(value))?;
+        }
+        writeln!(self.writer)?;
+
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.last_flush = Instant::now();
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+fn escape(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -1,6 +1,8 @@
+use std::fs::OpenOptions;
+use std::io::Write;
 use std::process::Command;
 use crate::display_types::{DisplayConfig, Orientation, ElementConfig, PositionConfig, 
-                           PositionValue, ComponentConfig, ValueConfig, PrefixSuffixConfig};
+                           PositionValue, ComponentConfig, ValueConfig};
 
 pub fn get_default_display_config() -> DisplayConfig {
     // Get hostname for the first line
@@ -11,6 +13,12 @@ pub fn get_default_display_config() -> DisplayConfig {
         orientation: Orientation::Landscape,  // Default to landscape orientation
         width: 128,                          // Standard width
         height: 32,                          // Standard height
+        fan_state_labels: None,
+        placeholder: None,
+        splash: None,
+        no_interface: None,
+        number_format: None,
+        net_counters: None,
         elements: vec![
             // Hostname on the first line
             ElementConfig {
@@ -27,8 +35,26 @@ pub fn get_default_display_config() -> DisplayConfig {
                         },
                         prefix: None,
                         suffix: None,
+                        track_changes: None,
+                        precision: None,
+                        color: None,
+                        thresholds: None,
+                        transform: None,
+                        letter_spacing: None,
+                        vertical: None,
+                        placeholder: None,
+                        zero: None,
                     },
                 ],
+                direction: None,
+                visible_when: None,
+                blink: None,
+                repeat: None,
+                border: None,
+                line: None,
+                rect: None,
+                wrap: None,
+                z: None,
             },
             // Hello World on the second line
             ElementConfig {
@@ -45,13 +71,47 @@ pub fn get_default_display_config() -> DisplayConfig {
                         },
                         prefix: None,
                         suffix: None,
+                        track_changes: None,
+                        precision: None,
+                        color: None,
+                        thresholds: None,
+                        transform: None,
+                        letter_spacing: None,
+                        vertical: None,
+                        placeholder: None,
+                        zero: None,
                     },
                 ],
+                direction: None,
+                visible_when: None,
+                blink: None,
+                repeat: None,
+                border: None,
+                line: None,
+                rect: None,
+                wrap: None,
+                z: None,
             },
         ],
     }
 }
 
+// Write the default config as pretty-printed JSON so users have a starting point to edit.
+// Refuses to overwrite an existing file.
+pub fn write_default_config(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let config = get_default_display_config();
+    let json = serde_json::to_string_pretty(&config)?;
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| format!("Cannot create {}: {}", path, e))?;
+    file.write_all(json.as_bytes())?;
+    file.write_all(b"\n")?;
+    Ok(())
+}
+
 fn get_system_hostname() -> String {
     // Try to get the system hostname
     match Command::new("hostname").output() {
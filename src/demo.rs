@@ -0,0 +1,212 @@
+use std::f32::consts::TAU;
+use std::time::Instant;
+
+use crate::metrics::{ByteUsage, HatInfo, InterfaceIp, IpCacheStatus, MetricsSource, NetErrors, ProcessUsage};
+use crate::throttle::ThrottleStatus;
+
+// Fixed readings, e.g. for rendering sample frames or in tests. The defaults look like a
+// lightly loaded Pi 4 with a VLAN interface.
+#[derive(Clone, Debug)]
+pub struct MockMetricsSource {
+    pub cpu_temperature: Option<f32>,
+    pub cpu_usage: f32,
+    pub ram_bytes: Option<ByteUsage>,
+    pub disk_bytes: Option<ByteUsage>,
+    pub load_average: f32,
+    pub throttle: ThrottleStatus,
+    pub processes: Vec<ProcessUsage>,
+    pub interfaces: Vec<InterfaceIp>,
+    pub net_errors: Option<NetErrors>,
+    pub poe: Option<bool>,
+    pub model: Option<String>,
+    pub hat: HatInfo,
+}
+
+impl Default for MockMetricsSource {
+    fn default() -> Self {
+        MockMetricsSource {
+            cpu_temperature: Some(48.3),
+            cpu_usage: 12.5,
+            ram_bytes: Some(ByteUsage { used: 1_488_000_000, total: 4_000_000_000 }),
+            disk_bytes: Some(ByteUsage { used: 19_520_000_000, total: 32_000_000_000 }),
+            load_average: 0.5,
+            throttle: ThrottleStatus::Ok,
+            processes: Vec::new(),
+            interfaces: vec![
+                ("eth0".to_string(), "192.168.1.42".to_string(), [192, 168, 1, 42]),
+                ("eth0.100".to_string(), "10.0.100.42".to_string(), [10, 0, 100, 42]),
+            ],
+            net_errors: Some(NetErrors { rx_errors: 0, tx_errors: 0, drops: 3 }),
+            poe: Some(true),
+            model: Some("Raspberry Pi 4 Model B Rev 1.4".to_string()),
+            hat: HatInfo { product: Some("PoE HAT (B)".to_string()), vendor: Some("Waveshare".to_string()) },
+        }
+    }
+}
+
+impl MetricsSource for MockMetricsSource {
+    fn cpu_temperature(&mut self) -> Option<f32> {
+        self.cpu_temperature
+    }
+
+    fn cpu_usage(&mut self) -> f32 {
+        self.cpu_usage
+    }
+
+    fn ram_bytes(&mut self) -> Option<ByteUsage> {
+        self.ram_bytes
+    }
+
+    fn disk_bytes(&mut self) -> Option<ByteUsage> {
+        self.disk_bytes
+    }
+
+    fn load_average(&mut self) -> f32 {
+        self.load_average
+    }
+
+    fn throttle_status(&mut self) -> ThrottleStatus {
+        self.throttle
+    }
+
+    fn processes(&mut self) -> Vec<ProcessUsage> {
+        self.processes.clone()
+    }
+
+    // Always the first interface
+    fn local_ip(&mut self) -> Option<InterfaceIp> {
+        self.interfaces.first().cloned()
+    }
+
+    fn local_ips(&mut self) -> Vec<InterfaceIp> {
+        self.interfaces.clone()
+    }
+
+    // The same counters for every interface
+    fn net_errors(&mut self, _interface: Option<&str>) -> Option<NetErrors> {
+        self.net_errors
+    }
+
+    fn poe_status(&mut self) -> Option<bool> {
+        self.poe
+    }
+
+    fn model(&mut self) -> Option<String> {
+        self.model.clone()
+    }
+
+    fn hat(&mut self) -> HatInfo {
+        self.hat.clone()
+    }
+
+    fn ip_cache_status(&mut self) -> IpCacheStatus {
+        IpCacheStatus { index: 0, count: self.interfaces.len(), age: None }
+    }
+}
+
+// Plausible readings that change over time, for trying out layouts and fan settings without
+// a Pi. The temperature sweeps between 40 and 70°C every two minutes, the CPU usage varies,
+// the address of the VLAN interface changes every 10 seconds and a packet is dropped every
+// 30 seconds.
+pub struct DemoMetrics {
+    started: Instant,
+}
+
+impl DemoMetrics {
+    pub fn new() -> Self {
+        DemoMetrics { started: Instant::now() }
+    }
+
+    // The readings at this moment
+    fn current(&self) -> MockMetricsSource {
+        let t = self.started.elapsed().as_secs_f32();
+        let wave = |period: f32| (t / period * TAU).sin();
+
+        let cpu_temperature = 55.0 + 15.0 * wave(120.0);
+        let cpu_usage = (30.0 + 25.0 * wave(17.0) + 10.0 * wave(5.0)).clamp(0.0, 100.0);
+        let ram_total = 4_000_000_000;
+        let octet = 100 + (t as u32 / 10 % 50) as u8;
+        MockMetricsSource {
+            cpu_temperature: Some(cpu_temperature),
+            cpu_usage,
+            ram_bytes: Some(ByteUsage { used: (ram_total as f32 * (0.40 + 0.05 * wave(60.0))) as u64, total: ram_total }),
+            // As if the CPU usage were spread over four cores
+            load_average: cpu_usage / 100.0 * 4.0,
+            // Like a Pi running hot
+            throttle: if cpu_temperature >= 68.0 { ThrottleStatus::Throttled } else { ThrottleStatus::Ok },
+            interfaces: vec![
+                ("eth0".to_string(), "192.168.1.42".to_string(), [192, 168, 1, 42]),
+                ("eth0.100".to_string(), format!("10.0.100.{}", octet), [10, 0, 100, octet]),
+            ],
+            net_errors: Some(NetErrors { drops: t as u64 / 30, ..NetErrors::default() }),
+            ..MockMetricsSource::default()
+        }
+    }
+}
+
+impl Default for DemoMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MetricsSource for DemoMetrics {
+    fn cpu_temperature(&mut self) -> Option<f32> {
+        self.current().cpu_temperature
+    }
+
+    fn cpu_usage(&mut self) -> f32 {
+        self.current().cpu_usage
+    }
+
+    fn ram_bytes(&mut self) -> Option<ByteUsage> {
+        self.current().ram_bytes
+    }
+
+    fn disk_bytes(&mut self) -> Option<ByteUsage> {
+        self.current().disk_bytes
+    }
+
+    fn load_average(&mut self) -> f32 {
+        self.current().load_average
+    }
+
+    fn throttle_status(&mut self) -> ThrottleStatus {
+        self.current().throttle
+    }
+
+    fn processes(&mut self) -> Vec<ProcessUsage> {
+        Vec::new()
+    }
+
+    // The VLAN interface, whose address changes
+    fn local_ip(&mut self) -> Option<InterfaceIp> {
+        self.current().interfaces.pop()
+    }
+
+    fn local_ips(&mut self) -> Vec<InterfaceIp> {
+        self.current().interfaces
+    }
+
+    fn net_errors(&mut self, _interface: Option<&str>) -> Option<NetErrors> {
+        self.current().net_errors
+    }
+
+    fn poe_status(&mut self) -> Option<bool> {
+        self.current().poe
+    }
+
+    fn model(&mut self) -> Option<String> {
+        self.current().model
+    }
+
+    fn hat(&mut self) -> HatInfo {
+        self.current().hat
+    }
+
+    // Always the VLAN interface, which is last
+    fn ip_cache_status(&mut self) -> IpCacheStatus {
+        let count = self.current().interfaces.len();
+        IpCacheStatus { index: count.saturating_sub(1), count, age: None }
+    }
+}
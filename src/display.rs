@@ -1,5 +1,5 @@
 use log::info;
-use crate::display_types::{DisplayConfig, Display, FONT_5X8, FONT_6X12, PCSENIOR8_STYLE, PROFONT12, PROFONT9, PositionValue};
+use crate::display_types::{DisplayConfig, Display, ElementConfig, LineConfig, RectConfig, ThresholdConfig, PanelSize, FONT_4X6, FONT_5X8, FONT_6X12, FONT_10X20, PCSENIOR8_STYLE, PROFONT12, PROFONT9, PositionValue};
 use linux_embedded_hal::I2cdev;
 use ssd1306::{prelude::*, I2CDisplayInterface, Ssd1306};
 use ssd1306::mode::DisplayConfig as SsdDisplayConfig;
@@ -8,31 +8,59 @@ use embedded_graphics::{
     pixelcolor::BinaryColor,
     prelude::*,
     mono_font::MonoTextStyle,
+    primitives::{Line, PrimitiveStyle, PrimitiveStyleBuilder, Rectangle, StrokeAlignment},
     text::Text
 };
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::convert::Infallible;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::fs::File;
 use std::io::Read;
-use serde_json::from_str;
-use log::{debug, error, warn};
+use std::path::Path;
+use log::{debug, error, trace, warn};
+
+use crate::bindings::{Binding, Bindings};
+use crate::framebuffer::FrameBuffer;
+use crate::metrics::{power_source_label, ByteUsage, InterfaceIp, MetricsSource, NetErrors, ProcessUsage};
+use crate::demo::MockMetricsSource;
+use crate::format::{convert_temp, TempUnit};
 
 // Use the default display config module
 use crate::default_config::get_default_display_config;
 
+// First delay between display initialization attempts at startup, doubled after each one
+const INIT_RETRY_DELAY: Duration = Duration::from_millis(500);
+
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum DisplayError {
-    InvalidOrientation,
     IoError(std::io::Error),
     JsonError(serde_json::Error),
+    // An I2C write was not acknowledged or failed on the bus. The interface doesn't say
+    // which, so this covers both a missing display (NACK) and a disturbed bus.
+    BusError,
+    // Any other display interface error; these aren't fixed by reconnecting
     DisplayError(display_interface::DisplayError),
     ConfigError(String),
 }
 
+impl DisplayError {
+    // Whether the display may have gone away, so reconnecting could help
+    pub fn is_bus_error(&self) -> bool {
+        matches!(self, DisplayError::BusError)
+    }
+}
+
 impl std::fmt::Display for DisplayError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            DisplayError::InvalidOrientation => write!(f, "Invalid orientation"),
             DisplayError::IoError(e) => write!(f, "IO error: {}", e),
             DisplayError::JsonError(e) => write!(f, "JSON error: {}", e),
+            DisplayError::BusError => write!(f, "I2C bus error: display not responding"),
             DisplayError::DisplayError(e) => write!(f, "Display error: {:?}", e),
             DisplayError::ConfigError(e) => write!(f, "Config error: {}", e),
         }
@@ -53,266 +81,1580 @@ impl From<serde_json::Error> for DisplayError {
     }
 }
 
+impl From<Infallible> for DisplayError {
+    fn from(error: Infallible) -> Self {
+        match error {}
+    }
+}
+
 impl From<InterfaceDisplayError> for DisplayError {
-    fn from(_error: InterfaceDisplayError) -> Self {
-        DisplayError::DisplayError(_error)
+    fn from(error: InterfaceDisplayError) -> Self {
+        match error {
+            InterfaceDisplayError::BusWriteError => DisplayError::BusError,
+            error => DisplayError::DisplayError(error),
+        }
     }
 }
 
+// Readings shown on the display. Unavailable ones are None.
+#[derive(Clone, Copy)]
+pub struct DisplayValues<'a> {
+    // Interfaces to show: normally just the current one, or all of them in "all" mode
+    pub interfaces: &'a [InterfaceIp],
+    pub cpu_usage: f32,
+    // Already converted to the display unit
+    pub cpu_temp: Option<f64>,
+    pub ram_usage: Option<f64>,
+    pub disk_usage: Option<f64>,
+    pub ram_bytes: Option<ByteUsage>,
+    pub disk_bytes: Option<ByteUsage>,
+    // For the proc_cpu(..) and proc_mem(..) bindings
+    pub processes: &'a [ProcessUsage],
+    pub throttled: Option<&'a str>,
+    pub poe: Option<&'a str>,
+    pub fan_running: bool,
+    pub model: Option<&'a str>,
+    pub hat_product: Option<&'a str>,
+    pub hat_vendor: Option<&'a str>,
+    pub net_errors: Option<NetErrors>,
+}
+
 pub struct PoeDisplay {
     display: Display,
     config: DisplayConfig,
+    // Last frame pushed to the panel; None until the first full flush
+    last_frame: Option<FrameBuffer>,
+    // Signature of the values shown in the last rendered frame
+    last_signature: Option<u64>,
+    panel_size: PanelSize,
+    // I2C address of the panel
+    address: u8,
+    // Most recent non-empty interface list, for the "keep_last" no-interface mode
+    last_interfaces: Vec<InterfaceIp>,
+    // Error counters at the previous frame and the interface they are from, for the "delta"
+    // net_counters mode
+    last_net_errors: Option<(Option<String>, NetErrors)>,
+    // Diagnostic lines drawn over the layout; empty while the overlay is off
+    overlay: Vec<String>,
+    // Whether the layout has a proc_cpu/proc_mem reading, worked out when it is loaded
+    shows_processes: bool,
 }
 
 impl PoeDisplay {
-    pub fn new(config_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+    // `init_retries` extra attempts are made to open the panel, with exponential backoff,
+    // since the I2C bus may not be ready yet right after boot
+    pub fn new(config_path: &str, panel_size: PanelSize, address: u8, init_retries: u32) -> Result<Self, Box<dyn std::error::Error>> {
         debug!("Initializing display with config path: {}", config_path);
         
-        // Attempt to load config from file
-        let config = match Self::load_config_from_file(config_path) {
-            Ok(config) => {
-                info!("Configuration loaded successfully from: {}", config_path);
-                config
-            },
-            Err(e) => {
-                // Log the error but continue with default config
-                warn!("Failed to load config from {}: {}", config_path, e);
-                warn!("Using default display configuration instead");
-                get_default_display_config()
-            }
-        };
+        let config = Self::load_config(config_path, panel_size)?;
+        let shows_processes = shows_processes(&config);
         
         debug!("Config details: orientation={:?}, width={}, height={}, elements={}", 
                config.orientation, config.width, config.height, config.elements.len());
         
-        // Initialize I2C
-        let i2c = I2cdev::new("/dev/i2c-1").map_err(|e| {
-            error!("Failed to initialize I2C device: {}", e);
-            e
-        })?;
-
-        // Initialize display with orientation from config
-        let display = initialize_display(i2c, &config)?;
+        let mut delay = INIT_RETRY_DELAY;
+        let mut attempt = 0;
+        let display = loop {
+            match open_display(&config, panel_size, address) {
+                Ok(display) => break display,
+                Err(e) if attempt < init_retries => {
+                    attempt += 1;
+                    warn!("Display initialization failed: {}. Retry {}/{} in {:?}", e, attempt, init_retries, delay);
+                    thread::sleep(delay);
+                    delay *= 2;
+                },
+                Err(e) => return Err(e),
+            }
+        };
         info!("Display initialized successfully");
 
-        Ok(PoeDisplay { display, config })
+        Ok(PoeDisplay { display, config, last_frame: None, last_signature: None, panel_size, address, last_interfaces: Vec::new(), last_net_errors: None, overlay: Vec::new(), shows_processes })
+    }
+
+    // Whether the process list has to be read for this layout
+    pub fn shows_processes(&self) -> bool {
+        self.shows_processes
+    }
+
+    // Re-open the I2C bus and re-initialize the panel, e.g. after it was unplugged.
+    // The panel contents are unknown afterwards, so the next frame is sent in full.
+    pub fn reconnect(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.display = open_display(&self.config, self.panel_size, self.address)?;
+        self.last_frame = None;
+        self.last_signature = None;
+        Ok(())
+    }
+    
+    // Turn the picture upside down, e.g. for a panel mounted the other way round, and
+    // re-initialize the panel with the new rotation
+    pub fn flip_orientation(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.config.orientation = self.config.orientation.flipped();
+        info!("Switching display orientation to {:?}", self.config.orientation);
+        self.reconnect()
+    }
+    
+    #[cfg(feature = "accelerometer")]
+    pub fn orientation(&self) -> crate::display_types::Orientation {
+        self.config.orientation
+    }
+    
+    // Turn the display to the given orientation, e.g. as reported by an accelerometer.
+    // Turning between landscape and portrait switches to the layout in `layout_path`, which
+    // has to be made for the new orientation; turning by 180° keeps the current layout.
+    #[cfg(feature = "accelerometer")]
+    pub fn set_orientation(&mut self, orientation: crate::display_types::Orientation, layout_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if orientation.is_portrait() != self.config.orientation.is_portrait() {
+            let mut config = Self::load_config(layout_path, self.panel_size)?;
+            config.orientation = orientation;
+            fit_layout_to_panel(&mut config, self.panel_size)?;
+            self.shows_processes = shows_processes(&config);
+            self.config = config;
+        } else {
+            self.config.orientation = orientation;
+        }
+        info!("Switching display orientation to {:?}", orientation);
+        self.reconnect()
     }
     
-    // Helper method to load config from file
-    fn load_config_from_file(config_path: &str) -> Result<DisplayConfig, Box<dyn std::error::Error>> {
-        info!("Loading config file from: {}", config_path);
+    // Load the layout as it is shown on a panel of this size, with the panel's width and
+    // height filled in. Falls back to the default layout if the file can't be read or parsed,
+    // but not if it doesn't fit on the panel.
+    pub fn load_config(config_path: &str, panel_size: PanelSize) -> Result<DisplayConfig, DisplayError> {
+        match Self::load_config_for_panel(config_path, panel_size) {
+            Ok(config) => {
+                info!("Configuration loaded successfully from: {}", config_path);
+                Ok(config)
+            },
+            Err(e) => match e.downcast::<DisplayError>() {
+                Ok(e) => Err(*e),
+                Err(e) => {
+                    // Log the error but continue with default config
+                    warn!("Failed to load config from {}: {}", config_path, e);
+                    warn!("Using default display configuration instead");
+                    let mut config = get_default_display_config();
+                    fit_layout_to_panel(&mut config, panel_size)?;
+                    Ok(config)
+                }
+            }
+        }
+    }
+
+    // Load the layout as it is shown on a panel of this size, without falling back to the
+    // default
+    pub fn load_config_for_panel(config_path: &str, panel_size: PanelSize) -> Result<DisplayConfig, Box<dyn std::error::Error>> {
+        let mut config = Self::load_config_from_file(config_path)?;
+        fit_layout_to_panel(&mut config, panel_size)?;
+        Ok(config)
+    }
+
+    // Load a layout without falling back to the default
+    pub fn load_config_from_file(config_path: &str) -> Result<DisplayConfig, Box<dyn std::error::Error>> {
+        debug!("Loading config file from: {}", config_path);
         let mut file = File::open(config_path)?;
 
-        let mut json_content = String::new();
-        file.read_to_string(&mut json_content)?;
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
 
-        info!("Parsing JSON config");
-        let config: DisplayConfig = from_str::<DisplayConfig>(&json_content)?;
+        // The format follows the file extension. Anything else is read as JSON5, a superset
+        // of JSON, so plain JSON layouts still load and hand-edited ones may use comments
+        // and trailing commas.
+        let extension = Path::new(config_path).extension().and_then(|e| e.to_str()).unwrap_or("");
+        let config: DisplayConfig = match extension {
+            #[cfg(feature = "yaml")]
+            "yaml" | "yml" => {
+                debug!("Parsing YAML config");
+                serde_yaml::from_str(&content)?
+            },
+            #[cfg(not(feature = "yaml"))]
+            "yaml" | "yml" => return Err("YAML layouts need a build with the yaml feature".into()),
+            #[cfg(feature = "toml")]
+            "toml" => {
+                debug!("Parsing TOML config");
+                toml::from_str(&content)?
+            },
+            #[cfg(not(feature = "toml"))]
+            "toml" => return Err("TOML layouts need a build with the toml feature".into()),
+            _ => {
+                debug!("Parsing JSON config");
+                json5::from_str(&content)?
+            },
+        };
         
-        info!("Configuration loaded with orientation: {:?}", config.orientation);
+        debug!("Configuration loaded with orientation: {:?}", config.orientation);
         Ok(config)
     }
 
-    pub fn update_display(
-        &mut self,
-        ip_info: &(String, String, [u8; 4]),
-        ip_address: &str,
-        interface: &str,
-        interface_phys: &str,
-        interface_numvlan: &str,
-        ip_octets: &[u8; 4],
-        cpu_usage: &String,
-        cpu_temp_str: &String,
-        ram_usage: &String,
-        disk_usage: &str,
-        display_orientation: &str,
-    ) -> Result<(), DisplayError> {
-        let disp = &mut self.display;
-    
-        // Always clear the entire display at the beginning
-        disp.clear(BinaryColor::Off)?;
+    // Draw the configured splash text centered on the panel. Returns how long it should stay
+    // up, or None if there is no splash.
+    pub fn show_splash(&mut self) -> Result<Option<Duration>, DisplayError> {
+        let Some(splash) = &self.config.splash else {
+            return Ok(None);
+        };
+        
+        let style = text_style(splash.font.as_deref().unwrap_or("FONT_6X12"), BinaryColor::On);
+        let width = splash.text.chars().count() as i32 * get_char_width_from_text_style(&style);
+        let (ascent, descent) = get_vertical_metrics_from_text_style(&style);
+        let position = Point::new(
+            (self.config.width - width) / 2,
+            (self.config.height - (ascent + descent)) / 2 + ascent,
+        );
         
-        // Iterate over elements
-        for element in &self.config.elements {
-            // First, prepare all components by resolving values and calculating their widths
-            struct PreparedComponent {
-                value_text: String,
-                value_font: MonoTextStyle<'static, BinaryColor>,
-                value_width: i32,
-                prefix_text: Option<String>,
-                prefix_font: Option<MonoTextStyle<'static, BinaryColor>>,
-                prefix_width: i32,
-                suffix_text: Option<String>,
-                suffix_font: Option<MonoTextStyle<'static, BinaryColor>>,
-                suffix_width: i32,
-                total_width: i32,
+        let mut frame = FrameBuffer::new(self.display.size());
+        Text::new(&splash.text, position, style).draw(&mut frame)?;
+        let duration = Duration::from_millis(splash.duration_ms.unwrap_or(2000));
+        self.flush_frame(frame)?;
+        debug!("Showing splash screen for {:?}", duration);
+        Ok(Some(duration))
+    }
+
+    // Render a frame with the current readings. Elements with "repeat": "interfaces" are
+    // drawn once per interface; all other elements use the first one. Returns whether the
+    // shown content changed.
+    pub fn update_display(&mut self, values: &DisplayValues) -> Result<bool, DisplayError> {
+        let keep_last = self.config.no_interface.as_ref().is_some_and(|n| n.mode == "keep_last");
+        if !values.interfaces.is_empty() {
+            if keep_last && self.last_interfaces.as_slice() != values.interfaces {
+                self.last_interfaces = values.interfaces.to_vec();
             }
-            
-            let mut prepared_components = Vec::new();
-            let mut total_element_width = 0;
-            
-            for component in &element.components {
-                // Resolve the actual value text
-                let value_text = match component.value.text.as_str() {
-                    "interface_phys" => interface_phys.to_string(),
-                    "interface_numvlan" => interface_numvlan.to_string(),
-                    "ip_info.0" => ip_info.0.clone(),
-                    "ip_octets(0)" => ip_octets[0].to_string(),
-                    "ip_octets(1)" => ip_octets[1].to_string(),
-                    "ip_octets(2)" => ip_octets[2].to_string(),
-                    "ip_octets(3)" => ip_octets[3].to_string(),
-                    "cpu_usage" => cpu_usage.clone(),
-                    "cpu_temp" => cpu_temp_str.clone(),
-                    "ram_usage" => ram_usage.clone(),
-                    "disk_usage" => disk_usage.to_string(),
-                    text => text.to_string(),
-                };
-                
-                // Get the font for the value - keep this exactly as it was
-                let value_font = match component.value.font.as_str() {
-                    "FONT_5X8" => FONT_5X8,
-                    "FONT_6X12" => FONT_6X12,
-                    "PCSENIOR8_STYLE" => PCSENIOR8_STYLE,
-                    "PROFONT12" => PROFONT12,
-                    "PROFONT9" => PROFONT9,
-                    _ => FONT_5X8,
-                };
-                
-                // Calculate value width
-                let char_width = get_char_width_from_text_style(&value_font);
-                let value_width = value_text.len() as i32 * char_width;
-                
-                // Process prefix if present
-                let (prefix_text, prefix_font, prefix_width) = if let Some(prefix) = &component.prefix {
-                    let prefix_font = match prefix.font.as_str() {
-                        "FONT_5X8" => FONT_5X8,
-                        "FONT_6X12" => FONT_6X12,
-                        "PCSENIOR8_STYLE" => PCSENIOR8_STYLE,
-                        "PROFONT12" => PROFONT12,
-                        "PROFONT9" => PROFONT9,
-                        _ => FONT_5X8,
-                    };
-                    
-                    let prefix_char_width = get_char_width_from_text_style(&prefix_font);
-                    let prefix_width = prefix.text.len() as i32 * prefix_char_width;
-                    
-                    (Some(prefix.text.clone()), Some(prefix_font), prefix_width)
-                } else {
-                    (None, None, 0)
-                };
-                
-                // Process suffix if present
-                let (suffix_text, suffix_font, suffix_width) = if let Some(suffix) = &component.suffix {
-                    let suffix_font = match suffix.font.as_str() {
-                        "FONT_5X8" => FONT_5X8,
-                        "FONT_6X12" => FONT_6X12,
-                        "PCSENIOR8_STYLE" => PCSENIOR8_STYLE,
-                        "PROFONT12" => PROFONT12,
-                        "PROFONT9" => PROFONT9,
-                        _ => FONT_5X8,
-                    };
-                    
-                    let suffix_char_width = get_char_width_from_text_style(&suffix_font);
-                    let suffix_width = suffix.text.len() as i32 * suffix_char_width;
-                    
-                    (Some(suffix.text.clone()), Some(suffix_font), suffix_width)
-                } else {
-                    (None, None, 0)
+        } else if keep_last && !self.last_interfaces.is_empty() {
+            trace!("No interface found, keeping the last known one");
+        }
+        // Deltas need a previous reading of the same interface, so they are unavailable on the
+        // first frame and when the shown interface changes
+        let net_errors = match self.config.net_counters.as_deref() {
+            Some("delta") => {
+                let interface = values.interfaces.first().map(|ip_info| ip_info.0.clone());
+                let delta = match (values.net_errors, &self.last_net_errors) {
+                    (Some(now), Some((before_interface, before))) if *before_interface == interface => Some(now.since(before)),
+                    _ => None,
                 };
-                
-                // Calculate total width for this component
-                let component_total_width = prefix_width + value_width + suffix_width;
-                total_element_width += component_total_width;
-                
-                // Store the prepared component
-                prepared_components.push(PreparedComponent {
-                    value_text,
-                    value_font,
-                    value_width,
-                    prefix_text,
-                    prefix_font,
-                    prefix_width,
-                    suffix_text,
-                    suffix_font,
-                    suffix_width,
-                    total_width: component_total_width,
-                });
+                self.last_net_errors = values.net_errors.map(|now| (interface, now));
+                delta
+            },
+            _ => values.net_errors,
+        };
+        let values = DisplayValues {
+            interfaces: if values.interfaces.is_empty() && keep_last { &self.last_interfaces } else { values.interfaces },
+            net_errors,
+            ..*values
+        };
+        let resolved = resolve_frame(&self.config, &values);
+
+        let mut hasher = DefaultHasher::new();
+        (frame_signature(&self.config, &resolved), &self.overlay).hash(&mut hasher);
+        let signature = hasher.finish();
+        if self.last_signature == Some(signature) {
+            trace!("Displayed values unchanged, skipping render");
+            return Ok(false);
+        }
+
+        // Compose the whole frame off-screen, then only push what changed to the panel
+        let mut frame = FrameBuffer::new(self.display.size());
+        draw_frame(&mut frame, &self.config, &resolved)?;
+        draw_overlay(&mut frame, &self.overlay)?;
+        
+        self.flush_frame(frame)?;
+        self.last_signature = Some(signature);
+        Ok(true)
+    }
+
+    // Show these diagnostic lines on top of the layout from the next frame on, or nothing
+    // if empty
+    pub fn set_overlay(&mut self, lines: Vec<String>) {
+        self.overlay = lines;
+    }
+
+    // Send a composed frame to the panel. Only pixels that differ from the last frame are
+    // written into the SSD1306 buffer, so its dirty-area tracking limits the flush to the
+    // changed region. Nothing is sent at all if the frame is unchanged.
+    //
+    // There is never an intermediate state on the panel: frames are composed off-screen, the
+    // SSD1306 buffer only receives the finished frame and a single flush() transfers it. The
+    // panel has no vsync, so a large transfer can still tear while it scans; keeping flushes
+    // to the changed region keeps that window short.
+    fn flush_frame(&mut self, frame: FrameBuffer) -> Result<(), DisplayError> {
+        let started = Instant::now();
+        match &self.last_frame {
+            Some(last_frame) => {
+                let changed: Vec<_> = frame.changed_pixels(last_frame).collect();
+                if changed.is_empty() {
+                    trace!("Frame unchanged, skipping flush");
+                    return Ok(());
+                }
+                trace!("Flushing {} changed pixels", changed.len());
+                self.display.draw_iter(changed)?;
+            },
+            None => {
+                // The panel contents are unknown, so send the full frame. clear() only marks
+                // the whole buffer dirty; it is never flushed on its own.
+                self.display.clear(BinaryColor::Off)?;
+                self.display.draw_iter(frame.pixels())?;
             }
-            
-            // Calculate the starting x position based on alignment
-            let x_position = match &element.position.x {
-                PositionValue::Text(val) => match val.as_str() {
-                    "center" => (self.config.width - total_element_width) / 2,
-                    "left" => 0,
-                    "right" => self.config.width - total_element_width,
-                    _ => 0,
-                },
-                PositionValue::Number(val) => *val,
-                PositionValue::Relative { align, anchor } => match align.as_str() {
-                    "center" => anchor - (total_element_width / 2),
-                    "left" => *anchor,
-                    "right" => anchor - total_element_width,
-                    _ => *anchor,
+        }
+
+        self.display.flush()?;
+        if self.last_frame.is_none() {
+            // The panel was kept dark since init and now holds a complete frame
+            self.display.set_display_on(true)?;
+        }
+        trace!("Flush took {:?}", started.elapsed());
+        self.last_frame = Some(frame);
+        Ok(())
+    }
+}
+
+// Component values, visibility and blink phase of every element for one frame
+struct ResolvedFrame {
+    // Indexed by element, then instance, then component
+    values: Vec<Vec<Vec<String>>>,
+    visible: Vec<bool>,
+    blink_on: Vec<bool>,
+    // Text drawn instead of the element's components, for the "text" no-interface mode
+    messages: Vec<Option<String>>,
+}
+
+// Render a single frame the size of the panel into an off-screen buffer, without opening
+// the display. Used to preview a layout.
+pub fn render_to_framebuffer(config: &mut DisplayConfig, values: &DisplayValues, panel_size: PanelSize) -> Result<FrameBuffer, DisplayError> {
+    fit_layout_to_panel(config, panel_size)?;
+    let (width, height) = oriented_dimensions(config, panel_size);
+    let mut frame = FrameBuffer::new(Size::new(width as u32, height as u32));
+    render(&mut frame, config, values)?;
+    Ok(frame)
+}
+
+// One frame of the layout with the fixed sample readings of the mock source, e.g. to preview
+// it or to compare it with a snapshot
+pub fn render_sample(config: &mut DisplayConfig, temp_unit: TempUnit, panel_size: PanelSize) -> Result<FrameBuffer, DisplayError> {
+    let mut source = MockMetricsSource::default();
+    let interfaces = source.local_ips();
+    let model = source.model();
+    let hat = source.hat();
+    let values = DisplayValues {
+        interfaces: &interfaces,
+        cpu_usage: source.cpu_usage(),
+        cpu_temp: source.cpu_temperature().map(|temp| convert_temp(temp, temp_unit)),
+        ram_usage: source.ram_usage(),
+        disk_usage: source.disk_usage(),
+        ram_bytes: source.ram_bytes(),
+        disk_bytes: source.disk_bytes(),
+        processes: &[],
+        throttled: Some(source.throttle_status().as_str()),
+        poe: source.poe_status().map(power_source_label),
+        fan_running: true,
+        model: model.as_deref(),
+        hat_product: hat.product.as_deref(),
+        hat_vendor: hat.vendor.as_deref(),
+        net_errors: source.net_errors(interfaces.first().map(|ip_info| ip_info.0.as_str())),
+    };
+    render_to_framebuffer(config, &values, panel_size)
+}
+
+// Draw one frame with the given readings onto any monochrome target, e.g. the panel itself,
+// an off-screen FrameBuffer or embedded-graphics' MockDisplay
+pub fn render<D>(target: &mut D, config: &DisplayConfig, values: &DisplayValues) -> Result<(), DisplayError>
+where
+    D: DrawTarget<Color = BinaryColor>,
+    DisplayError: From<D::Error>,
+{
+    draw_frame(target, config, &resolve_frame(config, values))
+}
+
+// Resolve every component's value up front so an unchanged frame can be skipped entirely
+// before drawing anything
+fn resolve_frame(config: &DisplayConfig, values: &DisplayValues) -> ResolvedFrame {
+    let interfaces = values.interfaces;
+    let placeholder = config.placeholder.as_deref().unwrap_or("--");
+    let primary = interfaces.first();
+    let fan_state = match (&config.fan_state_labels, values.fan_running) {
+        (Some(labels), true) => labels.on.as_str(),
+        (Some(labels), false) => labels.off.as_str(),
+        (None, true) => "ON",
+        (None, false) => "OFF",
+    };
+
+    // A value is either a binding name, literal text, or a template where each "{binding}"
+    // is substituted. Unavailable readings and unknown placeholders show the placeholder.
+    let resolve = |bindings: &Bindings, text: &str, precision: Option<usize>, placeholder: &str| -> String {
+        match bindings.get(text, precision) {
+            Some(value) => value.unwrap_or_else(|| placeholder.to_string()),
+            None if text.contains('{') => substitute_bindings(text, |name| {
+                bindings.get(name, precision).flatten().unwrap_or_else(|| placeholder.to_string())
+            }),
+            None => text.to_string(),
+        }
+    };
+
+    // Elements use the first interface, repeated ones get an instance per interface. A
+    // repeated element still gets one (placeholder) instance without any interfaces.
+    let number_format = config.number_format.clone().unwrap_or_default();
+    let primary_bindings = Bindings::new(values, fan_state, primary, &number_format);
+    let interface_bindings: Vec<Bindings> = interfaces.iter()
+        .map(|ip_info| Bindings::new(values, fan_state, Some(ip_info), &number_format))
+        .collect();
+
+    let resolved_values: Vec<Vec<Vec<String>>> = config.elements.iter().map(|element| {
+        let instances: Vec<&Bindings> = if element.repeat.as_deref() == Some("interfaces") && !interfaces.is_empty() {
+            interface_bindings.iter().collect()
+        } else {
+            vec![&primary_bindings]
+        };
+        instances.into_iter().map(|bindings| {
+            element.components.iter().map(|component| {
+                let placeholder = component.placeholder.as_deref().unwrap_or(placeholder);
+                let text = resolve(bindings, &component.value.text, component.precision, placeholder);
+                let text = match &component.zero {
+                    Some(zero) if text != placeholder && number_format.parse(&text) == Some(0.0) => zero.clone(),
+                    _ => text,
+                };
+                match component.transform.as_deref() {
+                    Some("upper") => text.to_uppercase(),
+                    Some("lower") => text.to_lowercase(),
+                    _ => text,
                 }
-            };
-            
-            let y_position = match &element.position.y {
-                PositionValue::Text(val) => match val.as_str() {
-                    "incrementing" => 0, // Could be improved
-                    _ => 0,
-                },
-                PositionValue::Number(val) => *val,
-                PositionValue::Relative { align: _, anchor } => *anchor,
-            };
+            }).collect()
+        }).collect()
+    }).collect();
+
+    // Elements showing interface details follow the no-interface mode while there is none
+    let no_interface = config.no_interface.as_ref().filter(|_| interfaces.is_empty());
+    let no_interface_mode = no_interface.map(|n| n.mode.as_str());
+    let messages: Vec<Option<String>> = config.elements.iter().map(|element| {
+        (no_interface_mode == Some("text") && shows_interface(element))
+            .then(|| no_interface.and_then(|n| n.text.clone()).unwrap_or_else(|| "No Network".to_string()))
+    }).collect();
+
+    // Evaluate visibility rules; hidden elements are skipped entirely, including layout
+    let visible: Vec<bool> = config.elements.iter().map(|element| {
+        if no_interface_mode == Some("hide") && shows_interface(element) {
+            return false;
+        }
+        match &element.visible_when {
+            Some(rule) => rule.matches(number_format.parse(&resolve(&primary_bindings, &rule.value, None, placeholder))),
+            None => true,
+        }
+    }).collect();
+
+    // Blinking elements keep their place in the layout but are only drawn in their "on" phase.
+    // The phase comes from the wall clock, so it can only change as often as frames are rendered.
+    let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+    let blink_on: Vec<bool> = config.elements.iter().map(|element| {
+        element.blink.as_ref().is_none_or(|blink| blink.is_on(now_ms))
+    }).collect();
+
+    ResolvedFrame { values: resolved_values, visible, blink_on, messages }
+}
+
+// Draw all visible elements of a resolved frame
+fn draw_frame<D>(target: &mut D, config: &DisplayConfig, resolved: &ResolvedFrame) -> Result<(), DisplayError>
+where
+    D: DrawTarget<Color = BinaryColor>,
+    DisplayError: From<D::Error>,
+{
+    // Elements can be placed relative to others with "anchor_ref", including ones further
+    // down the list, so measure every element before drawing. A forward reference sees the
+    // previous pass, so repeat until nothing moves; a chain needs at most one pass per
+    // element, anything longer means the references form a cycle.
+    let mut bounds = HashMap::new();
+    let mut settled = false;
+    for _ in 0..=config.elements.len() {
+        let mut measured = Layout { previous: bounds, ..Layout::default() };
+        lay_out_frame(target, config, resolved, &mut measured, None)?;
+        settled = measured.bounds == measured.previous;
+        bounds = measured.bounds;
+        if settled {
+            break;
+        }
+    }
+    if !settled {
+        return Err(DisplayError::ConfigError("Elements refer to each other with anchor_ref in a cycle".to_string()));
+    }
+
+    // One pass per z level, bottom first. Each pass lays out every element in list order, so
+    // the positions of stacked elements don't depend on what is drawn on top.
+    let mut levels: Vec<i32> = config.elements.iter().map(|element| element.z.unwrap_or(0)).collect();
+    levels.sort_unstable();
+    levels.dedup();
+    for level in levels {
+        let mut layout = Layout { previous: bounds.clone(), ..Layout::default() };
+        lay_out_frame(target, config, resolved, &mut layout, Some(level))?;
+    }
+    Ok(())
+}
+
+// Placement state carried from one element to the next while laying out a frame
+#[derive(Default)]
+struct Layout {
+    // Bottom of the previously laid out element, used to stack "incrementing" elements
+    y_cursor: i32,
+    // Outer box of every element laid out so far, by id, for "anchor_ref". A repeated
+    // element is represented by its first instance.
+    bounds: HashMap<String, Rectangle>,
+    // Outer boxes from the previous pass, for elements not laid out yet in this one
+    previous: HashMap<String, Rectangle>,
+}
+
+impl Layout {
+    fn record(&mut self, id: &str, bounds: Rectangle) {
+        self.bounds.entry(id.to_string()).or_insert(bounds);
+    }
+
+    // Right edge (x) or bottom (y) of a referenced element; 0 if there is no such element,
+    // e.g. because it is hidden, so the anchor counts from the layout's edge instead
+    fn reference(&self, anchor_ref: Option<&str>) -> (i32, i32) {
+        anchor_ref
+            .and_then(|id| self.bounds.get(id).or_else(|| self.previous.get(id)))
+            .map_or((0, 0), |bounds| (bounds.top_left.x + bounds.size.width as i32, bounds.top_left.y + bounds.size.height as i32))
+    }
+}
+
+// Place every element in list order; only the ones on `draw_level` are drawn, none if it
+// is None
+fn lay_out_frame<D>(target: &mut D, config: &DisplayConfig, resolved: &ResolvedFrame, layout: &mut Layout, draw_level: Option<i32>) -> Result<(), DisplayError>
+where
+    D: DrawTarget<Color = BinaryColor>,
+    DisplayError: From<D::Error>,
+{
+    for ((((element, instances), visible), blink_on), message) in config.elements.iter()
+        .zip(&resolved.values)
+        .zip(&resolved.visible)
+        .zip(&resolved.blink_on)
+        .zip(&resolved.messages)
+    {
+        let draw = draw_level == Some(element.z.unwrap_or(0));
+        if !*visible {
+            if draw {
+                trace!("Skipping hidden element: {}", element.id);
+            }
+            continue;
+        }
+        if draw {
+            if !*blink_on {
+                trace!("Element {} is in the off phase of its blink", element.id);
+            }
+            trace!("Rendering element: {}", element.id);
+        }
+
+        if let Some(message) = message {
+            draw_element(target, config, element, std::slice::from_ref(message), true, 0, layout, draw && *blink_on)?;
+            continue;
+        }
+        for (instance, values) in instances.iter().enumerate() {
+            draw_element(target, config, element, values, false, instance as i32, layout, draw && *blink_on)?;
+        }
+    }
+    Ok(())
+}
+
+// Whether any component of the element shows interface details, directly or in a template
+fn shows_interface(element: &ElementConfig) -> bool {
+    element.components.iter().any(|component| {
+        let text = &component.value.text;
+        Binding::ALL.iter()
+            .filter(|binding| binding.is_interface())
+            .any(|binding| text == binding.name() || text.contains(&format!("{{{}}}", binding.name())))
+    })
+}
+
+// Whether any component or visibility rule of the layout uses a proc_cpu/proc_mem reading
+fn shows_processes(config: &DisplayConfig) -> bool {
+    config.elements.iter().any(|element| {
+        element.components.iter().map(|component| &component.value.text)
+            .chain(element.visible_when.as_ref().map(|rule| &rule.value))
+            .any(|text| text.contains("proc_cpu(") || text.contains("proc_mem("))
+    })
+}
+
+// The threshold with the highest `above` that the value has reached, if any
+fn select_threshold(thresholds: &[ThresholdConfig], value: f64) -> Option<&ThresholdConfig> {
+    thresholds.iter()
+        .filter(|t| value >= t.above)
+        .max_by(|a, b| a.above.total_cmp(&b.above))
+}
+
+// Replace every "{name}" in `text` with `lookup(name)`. A "{" without a closing "}" is kept
+// as literal text.
+fn substitute_bindings(text: &str, lookup: impl Fn(&str) -> String) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        result.push_str(&rest[..start]);
+        result.push_str(&lookup(&rest[start + 1..start + len]));
+        rest = &rest[start + len + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+// Lay out one element (or one repeated instance of it) with its resolved component values,
+// advance the stacking cursor, and draw it unless it is blanked. A `bare` element leaves
+// out prefixes and suffixes.
+#[allow(clippy::too_many_arguments)]
+fn draw_element<D>(
+    disp: &mut D,
+    config: &DisplayConfig,
+    element: &ElementConfig,
+    values: &[String],
+    bare: bool,
+    instance: i32,
+    layout: &mut Layout,
+    draw: bool,
+) -> Result<(), DisplayError>
+where
+    D: DrawTarget<Color = BinaryColor>,
+    DisplayError: From<D::Error>,
+{
+    if let Some(line) = &element.line {
+        return draw_line(disp, config, element, line, layout, draw);
+    }
+    if let Some(rect) = &element.rect {
+        return draw_rect(disp, config, element, rect, layout, draw);
+    }
+    
+    // First, prepare all components by resolving values and calculating their widths
+    struct PreparedComponent {
+        value_text: String,
+        value_font: MonoTextStyle<'static, BinaryColor>,
+        value_width: i32,
+        prefix_text: Option<String>,
+        prefix_font: Option<MonoTextStyle<'static, BinaryColor>>,
+        prefix_width: i32,
+        suffix_text: Option<String>,
+        suffix_font: Option<MonoTextStyle<'static, BinaryColor>>,
+        suffix_width: i32,
+        letter_spacing: i32,
+        vertical: bool,
+        column_height: i32,
+    }
+    
+    let mut prepared_components = Vec::new();
+    let mut total_element_width = 0;
+    let number_format = config.number_format.clone().unwrap_or_default();
+    
+    for (component, value_text) in element.components.iter().zip(values) {
+        let value_text = value_text.clone();
+        
+        // A numeric value past one of the thresholds takes that threshold's style
+        let threshold = component.thresholds.as_deref()
+            .zip(number_format.parse(&value_text))
+            .and_then(|(thresholds, value)| select_threshold(thresholds, value));
+        
+        // Prefix, value and suffix share the component's color
+        let color = match threshold.and_then(|t| t.color.as_deref()).or(component.color.as_deref()) {
+            Some("off") => BinaryColor::Off,
+            _ => BinaryColor::On,
+        };
+        
+        let value_font = text_style(threshold.and_then(|t| t.font.as_deref()).unwrap_or(&component.value.font), color);
+        let letter_spacing = component.letter_spacing.unwrap_or(0);
+        
+        // Calculate value width
+        let value_width = text_width(&value_text, &value_font, letter_spacing);
+        
+        // Process prefix if present
+        let (prefix_text, prefix_font, prefix_width) = if let Some(prefix) = component.prefix.as_ref().filter(|_| !bare) {
+            let prefix_font = text_style(&prefix.font, color);
+            let prefix_width = text_width(&prefix.text, &prefix_font, letter_spacing);
             
-            // Draw all components with the correct positioning
-            let mut current_x = x_position;
+            (Some(prefix.text.clone()), Some(prefix_font), prefix_width)
+        } else {
+            (None, None, 0)
+        };
+        
+        // Process suffix if present
+        let (suffix_text, suffix_font, suffix_width) = if let Some(suffix) = component.suffix.as_ref().filter(|_| !bare) {
+            let suffix_font = text_style(&suffix.font, color);
+            let suffix_width = text_width(&suffix.text, &suffix_font, letter_spacing);
             
-            for component in prepared_components {
-                // Draw prefix if present
-                if let (Some(prefix_text), Some(prefix_font)) = (component.prefix_text, component.prefix_font) {
-                    Text::new(&prefix_text, Point::new(current_x, y_position), prefix_font).draw(disp)?;
-                    current_x += component.prefix_width;
-                }
-                
-                // Draw value
-                Text::new(&component.value_text, Point::new(current_x, y_position), component.value_font).draw(disp)?;
-                current_x += component.value_width;
-                
-                // Draw suffix if present
-                if let (Some(suffix_text), Some(suffix_font)) = (component.suffix_text, component.suffix_font) {
-                    Text::new(&suffix_text, Point::new(current_x, y_position), suffix_font).draw(disp)?;
-                    current_x += component.suffix_width;
+            (Some(suffix.text.clone()), Some(suffix_font), suffix_width)
+        } else {
+            (None, None, 0)
+        };
+        
+        // A vertical component stacks the characters of its prefix, value and suffix in a
+        // single column as wide as the widest character
+        let vertical = component.vertical.unwrap_or(false);
+        let (prefix_width, value_width, suffix_width, column_height) = if vertical {
+            let parts = [(prefix_text.as_deref(), prefix_font), (Some(value_text.as_str()), Some(value_font)), (suffix_text.as_deref(), suffix_font)];
+            let (width, height) = parts.iter()
+                .filter_map(|(text, font)| text.zip(*font))
+                .flat_map(|(text, font)| text.chars().map(move |_| font))
+                .fold((0, 0), |(width, height), font| {
+                    let (ascent, descent) = get_vertical_metrics_from_text_style(&font);
+                    (width.max(get_char_width_from_text_style(&font)), height + ascent + descent)
+                });
+            (0, width, 0, height)
+        } else {
+            (prefix_width, value_width, suffix_width, 0)
+        };
+        
+        // Calculate total width for this component
+        let component_total_width = prefix_width + value_width + suffix_width;
+        total_element_width += component_total_width;
+        
+        // Store the prepared component
+        prepared_components.push(PreparedComponent {
+            value_text,
+            value_font,
+            value_width,
+            prefix_text,
+            prefix_font,
+            prefix_width,
+            suffix_text,
+            suffix_font,
+            suffix_width,
+            letter_spacing,
+            vertical,
+            column_height,
+        });
+    }
+    
+    // A border and its padding surround the text, so alignment and stacking work on the
+    // outer box
+    let inset = element.border.as_ref().map_or(0, |border| border.thickness as i32 + border.padding);
+    
+    // Wrapped text is laid out character by character, each keeping its component's font,
+    // and the element is as wide as its widest line
+    let wrapped = element.wrap.unwrap_or(false).then(|| {
+        let glyphs: Vec<Glyph> = prepared_components.iter()
+            .flat_map(|c| {
+                [(c.prefix_text.as_deref(), c.prefix_font), (Some(c.value_text.as_str()), Some(c.value_font)), (c.suffix_text.as_deref(), c.suffix_font)]
+                    .into_iter()
+                    .filter_map(|(text, font)| text.zip(font))
+                    .flat_map(move |(text, font)| text.chars().map(move |ch| Glyph {
+                        ch,
+                        font,
+                        advance: get_char_width_from_text_style(&font) + c.letter_spacing,
+                    }))
+            })
+            .collect();
+        let lines = break_lines(&glyphs, config.width - 2 * inset);
+        (glyphs, lines)
+    });
+    if let Some((glyphs, lines)) = &wrapped {
+        total_element_width = lines.iter()
+            .map(|line| glyphs[line.clone()].iter().map(|glyph| glyph.advance).sum())
+            .max()
+            .unwrap_or(0);
+    }
+    let box_width = total_element_width + 2 * inset;
+    
+    // Calculate the starting x position based on alignment
+    let x_position = resolve_x(&element.position.x, config.width, box_width, layout);
+    
+    // Vertical extent of the element around its baseline, from the tallest fonts used
+    let (ascent, descent) = prepared_components.iter()
+        .flat_map(|c| std::iter::once(Some(c.value_font)).chain([c.prefix_font, c.suffix_font]))
+        .flatten()
+        .map(|font| get_vertical_metrics_from_text_style(&font))
+        .fold((0, 0), |(a, d), (fa, fd)| (a.max(fa), d.max(fd)));
+    let line_height = ascent + descent;
+    // Every wrapped line after the first adds a line below
+    let descent = match &wrapped {
+        Some((_, lines)) => descent + (lines.len() as i32 - 1).max(0) * line_height,
+        None => descent,
+    };
+    // Vertical components hang down from the top of the text
+    let descent = prepared_components.iter()
+        .filter(|c| c.vertical)
+        .map(|c| c.column_height - ascent)
+        .fold(descent, i32::max);
+    let (ascent, descent) = (ascent + inset, descent + inset);
+    
+    // Text is drawn on its baseline, so alignments place the baseline such that
+    // the element's top/middle/bottom lands where requested
+    let y_position = resolve_y(&element.position.y, config.height, ascent, descent, layout);
+    
+    // Repeated instances of an element are placed one line apart, unless the element
+    // is stacking anyway
+    let y_position = match &element.position.y {
+        PositionValue::Text(val) if val == "incrementing" => y_position,
+        _ => y_position + instance * (ascent + descent),
+    };
+    
+    // The next "incrementing" element starts below this one
+    layout.y_cursor = y_position + descent;
+    layout.record(&element.id, Rectangle::new(Point::new(x_position, y_position - ascent), Size::new(box_width.max(0) as u32, (ascent + descent).max(0) as u32)));
+    
+    if !draw {
+        return Ok(());
+    }
+    
+    if let Some(border) = &element.border {
+        draw_border(disp, config, border.thickness, x_position, y_position - ascent, box_width, ascent + descent)?;
+    }
+    
+    if let Some((glyphs, lines)) = wrapped {
+        let mut baseline = y_position;
+        for line in lines {
+            let mut x = x_position + inset;
+            for glyph in &glyphs[line] {
+                Text::new(glyph.ch.encode_utf8(&mut [0; 4]), Point::new(x, baseline), glyph.font).draw(disp)?;
+                x += glyph.advance;
+            }
+            baseline += line_height;
+        }
+        return Ok(());
+    }
+    
+    // Draw all components with the correct positioning. Right-to-left elements place the
+    // first component at the right edge, each component's own text still reading left to right
+    let rtl = element.direction.as_deref() == Some("rtl");
+    let text_x = x_position + inset;
+    let mut slot_x = text_x + total_element_width;
+    let mut current_x = text_x;
+    
+    for component in prepared_components {
+        if rtl {
+            slot_x -= component.prefix_width + component.value_width + component.suffix_width;
+            current_x = slot_x;
+        }
+        
+        if component.vertical {
+            let mut glyph_top = y_position - ascent + inset;
+            let parts = [(component.prefix_text, component.prefix_font), (Some(component.value_text), Some(component.value_font)), (component.suffix_text, component.suffix_font)];
+            for (text, font) in parts {
+                let (Some(text), Some(font)) = (text, font) else {
+                    continue;
+                };
+                let (glyph_ascent, glyph_descent) = get_vertical_metrics_from_text_style(&font);
+                for ch in text.chars() {
+                    Text::new(ch.encode_utf8(&mut [0; 4]), Point::new(current_x, glyph_top + glyph_ascent), font).draw(disp)?;
+                    glyph_top += glyph_ascent + glyph_descent;
                 }
             }
+            current_x += component.value_width;
+            continue;
         }
         
-        // Ensure the buffer is fully flushed to the display
-        disp.flush()?;
+        // Draw prefix if present
+        if let (Some(prefix_text), Some(prefix_font)) = (component.prefix_text, component.prefix_font) {
+            draw_text(disp, &prefix_text, Point::new(current_x, y_position), prefix_font, component.letter_spacing)?;
+            current_x += component.prefix_width;
+        }
         
-        Ok(())
+        // Draw value
+        draw_text(disp, &component.value_text, Point::new(current_x, y_position), component.value_font, component.letter_spacing)?;
+        current_x += component.value_width;
+        
+        // Draw suffix if present
+        if let (Some(suffix_text), Some(suffix_font)) = (component.suffix_text, component.suffix_font) {
+            draw_text(disp, &suffix_text, Point::new(current_x, y_position), suffix_font, component.letter_spacing)?;
+            current_x += component.suffix_width;
+        }
+    }
+    
+    Ok(())
+}
+
+// Diagnostic lines in the top left corner, in the smallest font and inverted so they stand
+// out from the layout underneath
+fn draw_overlay<D>(target: &mut D, lines: &[String]) -> Result<(), DisplayError>
+where
+    D: DrawTarget<Color = BinaryColor>,
+    DisplayError: From<D::Error>,
+{
+    let style = text_style("FONT_4X6", BinaryColor::Off);
+    let (ascent, descent) = get_vertical_metrics_from_text_style(&style);
+    for (row, line) in lines.iter().enumerate() {
+        Text::new(line, Point::new(0, ascent + row as i32 * (ascent + descent)), style).draw(target)?;
     }
+    Ok(())
+}
+
+// One character of wrapped text
+struct Glyph {
+    ch: char,
+    font: MonoTextStyle<'static, BinaryColor>,
+    // Character width plus letter spacing
+    advance: i32,
 }
 
-fn initialize_display(i2c: I2cdev, config: &DisplayConfig) -> Result<Display, Box<dyn std::error::Error>> {
-    let interface = I2CDisplayInterface::new(i2c);
+// Split glyphs into lines no wider than `max_width`, breaking after the last space that fits.
+// A word longer than a whole line is broken wherever the line is full. Every line gets at
+// least one glyph, even if that alone is too wide.
+fn break_lines(glyphs: &[Glyph], max_width: i32) -> Vec<Range<usize>> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let mut width = 0;
+    let mut last_space = None;
+    for (index, glyph) in glyphs.iter().enumerate() {
+        if width + glyph.advance > max_width && index > start {
+            // A space that doesn't fit ends the line by itself and is dropped
+            if glyph.ch == ' ' {
+                lines.push(start..index);
+                start = index + 1;
+                width = 0;
+                last_space = None;
+                continue;
+            }
+            match last_space {
+                // The space itself is dropped at the break
+                Some(space) => {
+                    lines.push(start..space);
+                    start = space + 1;
+                },
+                None => {
+                    lines.push(start..index);
+                    start = index;
+                },
+            }
+            last_space = None;
+            width = glyphs[start..index].iter().map(|glyph| glyph.advance).sum();
+            // What was carried over from a long word may still not fit with this glyph
+            if width + glyph.advance > max_width && index > start {
+                lines.push(start..index);
+                start = index;
+                width = 0;
+            }
+        }
+        if glyph.ch == ' ' {
+            last_space = Some(index);
+        }
+        width += glyph.advance;
+    }
+    lines.push(start..glyphs.len());
+    lines
+}
+
+// Hash the resolved values of all components that take part in change detection.
+// Components with `"track_changes": false` can still update, but only when some other value changes.
+fn frame_signature(config: &DisplayConfig, resolved: &ResolvedFrame) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    resolved.visible.hash(&mut hasher);
+    resolved.blink_on.hash(&mut hasher);
+    resolved.messages.hash(&mut hasher);
+    for (element, instances) in config.elements.iter().zip(&resolved.values) {
+        instances.len().hash(&mut hasher);
+        for values in instances {
+            for (component, value) in element.components.iter().zip(values) {
+                if component.track_changes.unwrap_or(true) {
+                    value.hash(&mut hasher);
+                }
+            }
+        }
+    }
+    hasher.finish()
+}
+
+// Fill in a layout width/height that was left out with the panel's, taking the rotation into
+// account, and make sure the layout fits on the panel
+fn fit_layout_to_panel(config: &mut DisplayConfig, panel_size: PanelSize) -> Result<(), DisplayError> {
+    let (panel_width, panel_height) = oriented_dimensions(config, panel_size);
+    if config.width == 0 {
+        config.width = panel_width;
+    }
+    if config.height == 0 {
+        config.height = panel_height;
+    }
+
+    // E.g. a portrait layout that still has the landscape 128x32 size
+    if config.orientation.is_portrait() != (config.height > config.width) && config.width != config.height {
+        warn!("Layout is {}x{}, which doesn't match its {:?} orientation; width and height may be swapped",
+              config.width, config.height, config.orientation);
+    }
+
+    if config.width > panel_width || config.height > panel_height {
+        return Err(DisplayError::ConfigError(format!(
+            "layout is {}x{} but the {:?} panel in {:?} orientation is {}x{}",
+            config.width, config.height, panel_size, config.orientation, panel_width, panel_height
+        )));
+    }
+    Ok(())
+}
+
+// Width and height of the panel as seen by the layout
+fn oriented_dimensions(config: &DisplayConfig, panel_size: PanelSize) -> (i32, i32) {
+    let (w, h) = panel_size.dimensions();
+    if config.orientation.is_portrait() {
+        (h, w)
+    } else {
+        (w, h)
+    }
+}
+
+fn open_display(config: &DisplayConfig, panel_size: PanelSize, address: u8) -> Result<Display, Box<dyn std::error::Error>> {
+    // Initialize I2C
+    let i2c = I2cdev::new("/dev/i2c-1").map_err(|e| {
+        error!("Failed to initialize I2C device: {}", e);
+        e
+    })?;
+
+    // Initialize display with orientation from config
+    initialize_display(i2c, config, panel_size, address)
+}
+
+fn initialize_display(i2c: I2cdev, config: &DisplayConfig, panel_size: PanelSize, address: u8) -> Result<Display, Box<dyn std::error::Error>> {
+    let interface = I2CDisplayInterface::new_custom_address(i2c, address);
 
     // Use rotation from config
     let rotation = config.orientation.to_display_rotation();
-    debug!("Initializing display with rotation: {:?} based on orientation: {:?}", 
-           rotation, config.orientation);
+    debug!("Initializing {:?} display at 0x{:02x} with rotation: {:?} based on orientation: {:?}", 
+           panel_size, address, rotation, config.orientation);
 
-    let mut disp = Ssd1306::new(interface, DisplaySize128x32, rotation)
-        .into_buffered_graphics_mode();
+    let mut disp = match panel_size {
+        PanelSize::Size128x32 => {
+            let mut disp = Ssd1306::new(interface, DisplaySize128x32, rotation)
+                .into_buffered_graphics_mode();
+            <Ssd1306<_, _, _> as SsdDisplayConfig>::init(&mut disp)
+                .map_err(DisplayError::from)?;
+            Display::Size128x32(Box::new(disp))
+        },
+        PanelSize::Size128x64 => {
+            let mut disp = Ssd1306::new(interface, DisplaySize128x64, rotation)
+                .into_buffered_graphics_mode();
+            <Ssd1306<_, _, _> as SsdDisplayConfig>::init(&mut disp)
+                .map_err(DisplayError::from)?;
+            Display::Size128x64(Box::new(disp))
+        },
+    };
 
-    <Ssd1306<_, _, _> as SsdDisplayConfig>::init(&mut disp)
-        .map_err(|e| format!("Display initialization error: {:?}", e))?;
+    // init() switches the panel on while its RAM still holds noise from power-up, or the
+    // last picture before a reconnect. Keep it dark until the first full frame is flushed.
+    disp.set_display_on(false).map_err(DisplayError::from)?;
     debug!("Display successfully initialized");
     Ok(disp)
 }
 
+// Draw a separator line. It is positioned like a text element whose box is the line itself,
+// with a numeric y giving its top row.
+fn draw_line<D>(disp: &mut D, config: &DisplayConfig, element: &ElementConfig, line: &LineConfig, layout: &mut Layout, draw: bool) -> Result<(), DisplayError>
+where
+    D: DrawTarget<Color = BinaryColor>,
+    DisplayError: From<D::Error>,
+{
+    let vertical = line.orientation == "vertical";
+    let (width, height) = if vertical {
+        (1, line.length.unwrap_or(config.height))
+    } else {
+        (line.length.unwrap_or(config.width), 1)
+    };
+    
+    let x = resolve_x(&element.position.x, config.width, width, layout);
+    let y = resolve_y(&element.position.y, config.height, 0, height, layout);
+    layout.y_cursor = y + height;
+    layout.record(&element.id, Rectangle::new(Point::new(x, y), Size::new(width.max(0) as u32, height.max(0) as u32)));
+    
+    if !draw || width <= 0 || height <= 0 {
+        return Ok(());
+    }
+    
+    Line::new(Point::new(x, y), Point::new(x + width - 1, y + height - 1))
+        .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+        .draw(disp)?;
+    Ok(())
+}
+
+// Draw a filled or outlined rectangle, e.g. as a background for inverted text. It is
+// positioned like a line, and clamped to the layout like a border.
+fn draw_rect<D>(disp: &mut D, config: &DisplayConfig, element: &ElementConfig, rect: &RectConfig, layout: &mut Layout, draw: bool) -> Result<(), DisplayError>
+where
+    D: DrawTarget<Color = BinaryColor>,
+    DisplayError: From<D::Error>,
+{
+    let width = rect.width.unwrap_or(config.width);
+    let height = rect.height.unwrap_or(config.height);
+    
+    let x = resolve_x(&element.position.x, config.width, width, layout);
+    let y = resolve_y(&element.position.y, config.height, 0, height, layout);
+    layout.y_cursor = y + height;
+    layout.record(&element.id, Rectangle::new(Point::new(x, y), Size::new(width.max(0) as u32, height.max(0) as u32)));
+    
+    if !draw {
+        return Ok(());
+    }
+    if rect.fill != Some(true) {
+        return draw_border(disp, config, rect.stroke_width.unwrap_or(1), x, y, width, height);
+    }
+    if let Some(area) = clamp_to_layout(config, x, y, width, height) {
+        area.into_styled(PrimitiveStyle::with_fill(BinaryColor::On)).draw(disp)?;
+    }
+    Ok(())
+}
+
+// Left edge of a box of the given width
+fn resolve_x(position: &PositionValue, layout_width: i32, width: i32, layout: &Layout) -> i32 {
+    match position {
+        PositionValue::Text(val) => match val.as_str() {
+            "center" => (layout_width - width) / 2,
+            "left" => 0,
+            "right" => layout_width - width,
+            _ => 0,
+        },
+        // Negative values count from the right edge: -1 puts the element's right end on
+        // the last column
+        PositionValue::Number(val) if *val < 0 => layout_width + val + 1 - width,
+        PositionValue::Number(val) => *val,
+        PositionValue::Relative { align, anchor, anchor_ref } => {
+            let anchor = layout.reference(anchor_ref.as_deref()).0 + anchor;
+            match align.as_str() {
+                "center" => anchor - (width / 2),
+                "right" => anchor - width,
+                _ => anchor,
+            }
+        },
+        PositionValue::Percent { percent } => (layout_width as f32 * percent / 100.0).round() as i32,
+    }
+}
+
+// Baseline of a box extending `ascent` above and `descent` below it
+fn resolve_y(position: &PositionValue, layout_height: i32, ascent: i32, descent: i32, layout: &Layout) -> i32 {
+    match position {
+        PositionValue::Text(val) => match val.as_str() {
+            "top" => ascent,
+            "middle" => (layout_height - (ascent + descent)) / 2 + ascent,
+            "bottom" => layout_height - descent,
+            "incrementing" => layout.y_cursor + ascent,
+            _ => 0,
+        },
+        // Negative values count from the bottom edge: -1 puts the element's bottom on the
+        // last row
+        PositionValue::Number(val) if *val < 0 => layout_height + val + 1 - descent,
+        PositionValue::Number(val) => *val,
+        PositionValue::Relative { anchor, anchor_ref: None, .. } => *anchor,
+        // The element's top goes `anchor` rows below the referenced element
+        PositionValue::Relative { anchor, anchor_ref: Some(id), .. } => layout.reference(Some(id)).1 + anchor + ascent,
+        PositionValue::Percent { percent } => (layout_height as f32 * percent / 100.0).round() as i32,
+    }
+}
+
+// Draw a rectangular frame with the stroke inside the given box. The box is clamped to the
+// layout so a border near the edge stays visible instead of being cut off.
+fn draw_border<D>(disp: &mut D, config: &DisplayConfig, thickness: u32, x: i32, y: i32, width: i32, height: i32) -> Result<(), DisplayError>
+where
+    D: DrawTarget<Color = BinaryColor>,
+    DisplayError: From<D::Error>,
+{
+    let Some(area) = clamp_to_layout(config, x, y, width, height) else {
+        return Ok(());
+    };
+    
+    let style = PrimitiveStyleBuilder::new()
+        .stroke_color(BinaryColor::On)
+        .stroke_width(thickness)
+        .stroke_alignment(StrokeAlignment::Inside)
+        .build();
+    area.into_styled(style).draw(disp)?;
+    Ok(())
+}
+
+// The part of a box that lies within the layout; None if none of it does
+fn clamp_to_layout(config: &DisplayConfig, x: i32, y: i32, width: i32, height: i32) -> Option<Rectangle> {
+    let left = x.max(0);
+    let top = y.max(0);
+    let right = (x + width).min(config.width);
+    let bottom = (y + height).min(config.height);
+    (right > left && bottom > top)
+        .then(|| Rectangle::new(Point::new(left, top), Size::new((right - left) as u32, (bottom - top) as u32)))
+}
+
+// Text style for a font name, falling back to FONT_5X8 for unknown names. "Off" text is
+// drawn as unlit characters on lit character cells, for inverted badge-style labels.
+fn text_style(font: &str, color: BinaryColor) -> MonoTextStyle<'static, BinaryColor> {
+    let mut style = match font {
+        "FONT_4X6" => FONT_4X6,
+        "FONT_5X8" => FONT_5X8,
+        "FONT_6X12" => FONT_6X12,
+        "FONT_10X20" => FONT_10X20,
+        "PCSENIOR8_STYLE" => PCSENIOR8_STYLE,
+        "PROFONT12" => PROFONT12,
+        "PROFONT9" => PROFONT9,
+        _ => FONT_5X8,
+    };
+    if color == BinaryColor::Off {
+        style.text_color = Some(BinaryColor::Off);
+        style.background_color = Some(BinaryColor::On);
+    }
+    style
+}
+
 fn get_char_width_from_text_style<'a>(font_style: &MonoTextStyle<'a, BinaryColor>) -> i32 {
     // Get the character width from the font's metadata
     // This includes both the character size and any additional spacing
     font_style.font.character_size.width as i32 + font_style.font.character_spacing as i32
-}
\ No newline at end of file
+}
+
+// Width of `text` with `letter_spacing` extra pixels after every character
+fn text_width(text: &str, font_style: &MonoTextStyle<'_, BinaryColor>, letter_spacing: i32) -> i32 {
+    text.chars().count() as i32 * (get_char_width_from_text_style(font_style) + letter_spacing)
+}
+
+// Draw `text` with its baseline at `position`. With extra letter spacing each glyph is
+// drawn on its own, advancing by the same amount text_width() counts per character.
+fn draw_text<D>(disp: &mut D, text: &str, position: Point, font_style: MonoTextStyle<'static, BinaryColor>, letter_spacing: i32) -> Result<(), DisplayError>
+where
+    D: DrawTarget<Color = BinaryColor>,
+    DisplayError: From<D::Error>,
+{
+    if letter_spacing == 0 {
+        Text::new(text, position, font_style).draw(disp)?;
+        return Ok(());
+    }
+    
+    let advance = get_char_width_from_text_style(&font_style) + letter_spacing;
+    for (index, ch) in text.chars().enumerate() {
+        let glyph_position = position + Point::new(index as i32 * advance, 0);
+        Text::new(ch.encode_utf8(&mut [0; 4]), glyph_position, font_style).draw(disp)?;
+    }
+    Ok(())
+}
+
+fn get_vertical_metrics_from_text_style<'a>(font_style: &MonoTextStyle<'a, BinaryColor>) -> (i32, i32) {
+    // Rows above the baseline, and rows from the baseline down, so a glyph drawn at
+    // baseline y covers rows y - ascent ..= y + descent - 1
+    let ascent = font_style.font.baseline as i32;
+    let descent = font_style.font.character_size.height as i32 - ascent;
+    (ascent, descent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display_types::Orientation;
+    use crate::format::NumberFormat;
+
+    fn text(value: &str) -> PositionValue {
+        PositionValue::Text(value.to_string())
+    }
+
+    // Render a layout given as JSON with the test readings on a 128x32 panel
+    fn render_layout(json: &str) -> FrameBuffer {
+        let mut config: DisplayConfig = serde_json::from_str(json).unwrap();
+        render_to_framebuffer(&mut config, &values(), PanelSize::Size128x32).unwrap()
+    }
+
+    fn values() -> DisplayValues<'static> {
+        DisplayValues {
+            interfaces: &[],
+            cpu_usage: 12.5,
+            cpu_temp: Some(48.25),
+            ram_usage: Some(37.0),
+            disk_usage: None,
+            ram_bytes: None,
+            disk_bytes: None,
+            processes: &[],
+            throttled: Some("OK"),
+            poe: None,
+            fan_running: false,
+            model: None,
+            hat_product: None,
+            hat_vendor: None,
+            net_errors: None,
+        }
+    }
+
+    #[test]
+    fn resolve_y_aligns_to_the_panel_height() {
+        let layout = Layout::default();
+        // FONT_6X12: 9 rows above the baseline, 3 from it down
+        let (ascent, descent) = get_vertical_metrics_from_text_style(&FONT_6X12);
+        assert_eq!((ascent, descent), (9, 3));
+
+        for (height, middle) in [(32, 19), (64, 35)] {
+            assert_eq!(resolve_y(&text("top"), height, ascent, descent, &layout), 9);
+            assert_eq!(resolve_y(&text("middle"), height, ascent, descent, &layout), middle);
+            assert_eq!(resolve_y(&text("bottom"), height, ascent, descent, &layout), height - 3);
+        }
+    }
+
+    #[test]
+    fn negative_y_counts_from_the_bottom() {
+        let layout = Layout::default();
+        for height in [32, 64] {
+            // -1 puts the bottom row of the text on the last row of the panel
+            assert_eq!(resolve_y(&PositionValue::Number(-1), height, 10, 2, &layout), height - 2);
+            assert_eq!(resolve_y(&PositionValue::Number(-5), height, 10, 2, &layout), height - 6);
+            assert_eq!(resolve_y(&PositionValue::Number(12), height, 10, 2, &layout), 12);
+        }
+    }
+
+    #[test]
+    fn font_names_pick_their_fonts() {
+        let cases = [("FONT_4X6", 4, 6), ("FONT_5X8", 5, 8), ("FONT_6X12", 6, 12), ("FONT_10X20", 10, 20)];
+        for (font, width, height) in cases {
+            let style = text_style(font, BinaryColor::On);
+            assert_eq!(get_char_width_from_text_style(&style), width, "{}", font);
+            assert_eq!(style.font.character_size.height, height, "{}", font);
+        }
+        // Unknown names fall back to FONT_5X8
+        assert_eq!(get_char_width_from_text_style(&text_style("FONT_4x6", BinaryColor::On)), 5);
+    }
+
+    #[test]
+    fn degree_sign_counts_as_one_character() {
+        let fonts = ["FONT_4X6", "FONT_5X8", "FONT_6X12", "FONT_10X20", "PCSENIOR8_STYLE", "PROFONT12", "PROFONT9"];
+        for font in fonts {
+            let style = text_style(font, BinaryColor::On);
+            let advance = get_char_width_from_text_style(&style);
+            // "°" is two bytes in UTF-8 but a single glyph
+            assert_eq!(text_width("48.3°C", &style, 0), 6 * advance, "{}", font);
+            assert_eq!(text_width("48.3°C", &style, 1), 6 * (advance + 1), "{}", font);
+            // embedded-graphics also advances by one glyph for it
+            let mut frame = FrameBuffer::new(Size::new(128, 32));
+            let with_degree = Text::new("48.3°C", Point::new(0, 20), style).draw(&mut frame).unwrap();
+            let ascii = Text::new("48.3oC", Point::new(0, 20), style).draw(&mut frame).unwrap();
+            assert_eq!(with_degree, ascii, "{}", font);
+        }
+    }
+
+    #[test]
+    fn incrementing_y_stacks_below_the_previous_element() {
+        let layout = Layout { y_cursor: 12, ..Layout::default() };
+        assert_eq!(resolve_y(&text("incrementing"), 32, 10, 2, &layout), 22);
+    }
+
+    #[test]
+    fn precision_sets_the_decimals_and_the_width() {
+        let number_format = NumberFormat::default();
+        let bindings = Bindings::new(&values(), "OFF", None, &number_format);
+        let style = text_style("FONT_6X12", BinaryColor::On);
+        for (precision, expected) in [(Some(0), "48"), (Some(1), "48.3"), (None, "48.3"), (Some(2), "48.25")] {
+            let text = bindings.get("cpu_temp", precision).flatten().unwrap();
+            assert_eq!(text, expected);
+            assert_eq!(text_width(&text, &style, 0), 6 * expected.len() as i32);
+        }
+        assert_eq!(bindings.get("cpu_usage", Some(0)).flatten().unwrap(), "13");
+        // Text bindings aren't numbers
+        assert_eq!(bindings.get("throttled", Some(2)).flatten().unwrap(), "OK");
+    }
+
+    #[test]
+    fn select_threshold_picks_the_highest_one_reached() {
+        let threshold = |above: f64, font: &str| ThresholdConfig { above, font: Some(font.to_string()), color: None };
+        // Out of order on purpose
+        let thresholds = [threshold(80.0, "hot"), threshold(60.0, "warm")];
+        let font = |value: f64| select_threshold(&thresholds, value).and_then(|t| t.font.as_deref());
+
+        assert_eq!(font(-10.0), None);
+        assert_eq!(font(59.9), None);
+        // `above` itself counts as reached
+        assert_eq!(font(60.0), Some("warm"));
+        assert_eq!(font(79.99), Some("warm"));
+        assert_eq!(font(80.0), Some("hot"));
+        assert_eq!(font(120.0), Some("hot"));
+        assert_eq!(select_threshold(&[], 100.0).map(|t| t.above), None);
+    }
+
+    #[test]
+    fn portrait_layouts_get_the_rotated_panel_size() {
+        let cases = [
+            (Orientation::Landscape, PanelSize::Size128x32, (128, 32)),
+            (Orientation::LandscapeFlipped, PanelSize::Size128x64, (128, 64)),
+            (Orientation::Portrait, PanelSize::Size128x32, (32, 128)),
+            (Orientation::PortraitFlipped, PanelSize::Size128x64, (64, 128)),
+        ];
+        for (orientation, panel_size, (width, height)) in cases {
+            let mut config = get_default_display_config();
+            config.orientation = orientation;
+            config.width = 0;
+            config.height = 0;
+            fit_layout_to_panel(&mut config, panel_size).unwrap();
+            assert_eq!((config.width, config.height), (width, height), "{:?} on {:?}", orientation, panel_size);
+
+            // Aligned to the rotated edges, e.g. a 20 pixel wide text
+            let layout = Layout::default();
+            assert_eq!(resolve_x(&text("center"), config.width, 20, &layout), (width - 20) / 2);
+            assert_eq!(resolve_x(&text("right"), config.width, 20, &layout), width - 20);
+            assert_eq!(resolve_y(&text("bottom"), config.height, 9, 3, &layout), height - 3);
+        }
+    }
+
+    #[test]
+    fn landscape_sized_layout_does_not_fit_in_portrait() {
+        let mut config = get_default_display_config();
+        config.orientation = Orientation::Portrait;
+        assert_eq!((config.width, config.height), (128, 32));
+        assert!(matches!(fit_layout_to_panel(&mut config, PanelSize::Size128x32), Err(DisplayError::ConfigError(_))));
+    }
+
+    #[test]
+    fn vertical_text_stacks_the_characters() {
+        let frame = render_layout(r#"{
+            "orientation": "landscape",
+            "elements": [{
+                "id": "label",
+                "position": { "x": 10, "y": "top" },
+                "components": [{ "value": { "text": "AB", "font": "FONT_6X12" }, "vertical": true }]
+            }]
+        }"#);
+
+        // One glyph below the other, each as tall as the font
+        let style = text_style("FONT_6X12", BinaryColor::On);
+        let mut expected = FrameBuffer::new(Size::new(128, 32));
+        Text::new("A", Point::new(10, 9), style).draw(&mut expected).unwrap();
+        Text::new("B", Point::new(10, 21), style).draw(&mut expected).unwrap();
+        assert!(frame == expected, "got:\n{}", frame.to_text());
+        assert_eq!(frame.clipped(), 0);
+    }
+
+    #[test]
+    fn unavailable_and_zero_readings_have_their_own_texts() {
+        let config: DisplayConfig = serde_json::from_str(r#"{
+            "orientation": "landscape",
+            "placeholder": "??",
+            "elements": [{
+                "id": "readings",
+                "position": { "x": 0, "y": "top" },
+                "components": [
+                    { "value": { "text": "cpu_temp", "font": "FONT_5X8" }, "placeholder": "ERR", "zero": "cold" },
+                    { "value": { "text": "cpu_usage", "font": "FONT_5X8" }, "placeholder": "ERR", "zero": "idle" },
+                    { "value": { "text": "ram_usage", "font": "FONT_5X8" }, "zero": "none" },
+                    { "value": { "text": "disk_usage", "font": "FONT_5X8" } }
+                ]
+            }]
+        }"#).unwrap();
+        let resolve = |values: &DisplayValues| resolve_frame(&config, values).values[0][0].clone();
+
+        // An unavailable reading shows the component's placeholder, or else the layout's
+        let unavailable = DisplayValues { cpu_temp: None, ..values() };
+        assert_eq!(resolve(&unavailable), ["ERR", "12.5", "37.0", "??"]);
+        // A genuine zero shows the zero text, other readings as they are
+        let zero = DisplayValues { cpu_usage: 0.0, ram_usage: Some(0.0), disk_usage: Some(0.0), ..values() };
+        assert_eq!(resolve(&zero), ["48.3", "idle", "none", "0.0"]);
+        let cold = DisplayValues { cpu_temp: Some(0.0), ..values() };
+        assert_eq!(resolve(&cold)[0], "cold");
+    }
+
+    #[test]
+    fn process_readings_are_found_in_the_layout() {
+        let layout = |text: &str, rule: &str| -> DisplayConfig {
+            serde_json::from_str(&format!(r#"{{
+                "orientation": "landscape",
+                "elements": [{{
+                    "id": "nginx",
+                    "position": {{ "x": 0, "y": "top" }},
+                    "visible_when": {{ "value": "{}", "op": ">", "threshold": 1 }},
+                    "components": [{{ "value": {{ "text": "{}", "font": "FONT_5X8" }} }}]
+                }}]
+            }}"#, rule, text)).unwrap()
+        };
+
+        assert!(shows_processes(&layout("proc_cpu(nginx)", "cpu_usage")));
+        assert!(shows_processes(&layout("nginx {proc_mem(nginx)}", "cpu_usage")));
+        assert!(shows_processes(&layout("nginx", "proc_cpu(nginx)")));
+        assert!(!shows_processes(&layout("{cpu_usage}% proc", "cpu_usage")));
+        assert!(!shows_processes(&get_default_display_config()));
+    }
+
+    #[test]
+    fn transform_applies_before_measuring_the_width() {
+        let layout = r#"{
+            "orientation": "landscape",
+            "elements": [{
+                "id": "street",
+                "position": { "x": "right", "y": "top" },
+                "components": [
+                    { "value": { "text": "Straße", "font": "FONT_6X12" }, "transform": "upper" },
+                    { "value": { "text": "throttled", "font": "FONT_6X12" }, "transform": "lower" }
+                ]
+            }]
+        }"#;
+        let config: DisplayConfig = serde_json::from_str(layout).unwrap();
+        let resolved = resolve_frame(&config, &values());
+        assert_eq!(resolved.values[0][0], ["STRASSE", "ok"]);
+
+        // "ß" becomes two characters, so the right-aligned text starts one glyph further left
+        let style = text_style("FONT_6X12", BinaryColor::On);
+        let width = text_width("STRASSEok", &style, 0);
+        assert_eq!(width, 9 * 6);
+        let mut expected = FrameBuffer::new(Size::new(128, 32));
+        Text::new("STRASSEok", Point::new(128 - width, 9), style).draw(&mut expected).unwrap();
+        let frame = render_layout(layout);
+        assert!(frame == expected, "got:\n{}", frame.to_text());
+        assert_eq!(frame.clipped(), 0);
+    }
+
+    #[test]
+    fn letter_spacing_advances_like_the_width() {
+        for font in ["FONT_5X8", "FONT_6X12", "PROFONT9"] {
+            let style = text_style(font, BinaryColor::On);
+            let advance = get_char_width_from_text_style(&style);
+            for spacing in [0, 1, 3] {
+                let mut frame = FrameBuffer::new(Size::new(128, 32));
+                draw_text(&mut frame, "4.2", Point::new(5, 20), style, spacing).unwrap();
+
+                // Each glyph starts where text_width() of the text before it ends
+                let mut expected = FrameBuffer::new(Size::new(128, 32));
+                for (index, glyph) in ["4", ".", "2"].iter().enumerate() {
+                    let x = 5 + text_width(&"4.2"[..index], &style, spacing);
+                    Text::new(glyph, Point::new(x, 20), style).draw(&mut expected).unwrap();
+                }
+                assert!(frame == expected, "{} with spacing {}", font, spacing);
+                assert_eq!(text_width("4.2", &style, spacing), 3 * (advance + spacing));
+            }
+        }
+    }
+
+    #[test]
+    fn anchor_refs_may_point_down_a_chain() {
+        // Each element refers to the next one in the list, so the positions only settle
+        // after a pass per element
+        let frame = render_layout(r#"{
+            "orientation": "landscape",
+            "elements": [
+                { "id": "a", "position": { "x": { "align": "left", "anchor_ref": "b", "anchor": 2 }, "y": "top" },
+                  "components": [{ "value": { "text": "A", "font": "FONT_6X12" } }] },
+                { "id": "b", "position": { "x": { "align": "left", "anchor_ref": "c", "anchor": 2 }, "y": "top" },
+                  "components": [{ "value": { "text": "B", "font": "FONT_6X12" } }] },
+                { "id": "c", "position": { "x": 3, "y": "top" },
+                  "components": [{ "value": { "text": "C", "font": "FONT_6X12" } }] }
+            ]
+        }"#);
+
+        let style = text_style("FONT_6X12", BinaryColor::On);
+        let mut expected = FrameBuffer::new(Size::new(128, 32));
+        for (glyph, x) in [("C", 3), ("B", 11), ("A", 19)] {
+            Text::new(glyph, Point::new(x, 9), style).draw(&mut expected).unwrap();
+        }
+        assert!(frame == expected, "got:\n{}", frame.to_text());
+    }
+
+    #[test]
+    fn anchor_ref_cycles_are_an_error() {
+        let mut config: DisplayConfig = serde_json::from_str(r#"{
+            "orientation": "landscape",
+            "elements": [
+                { "id": "a", "position": { "x": { "align": "left", "anchor_ref": "b", "anchor": 1 }, "y": "top" },
+                  "components": [{ "value": { "text": "A", "font": "FONT_6X12" } }] },
+                { "id": "b", "position": { "x": { "align": "left", "anchor_ref": "a", "anchor": 1 }, "y": "top" },
+                  "components": [{ "value": { "text": "B", "font": "FONT_6X12" } }] }
+            ]
+        }"#).unwrap();
+        let result = render_to_framebuffer(&mut config, &values(), PanelSize::Size128x32);
+        assert!(matches!(result, Err(DisplayError::ConfigError(_))));
+    }
+
+    #[test]
+    fn layouts_may_have_comments_and_trailing_commas() {
+        let commented = r#"{
+            // Hand-edited
+            "orientation": "landscape",
+            "elements": [{
+                "id": "temp", /* the CPU temperature */
+                "position": { "x": "right", "y": "top", },
+                "components": [{ "value": { "text": "cpu_temp", "font": "FONT_6X12", }, },],
+            },],
+        }"#;
+        let plain = r#"{
+            "orientation": "landscape",
+            "elements": [{
+                "id": "temp",
+                "position": { "x": "right", "y": "top" },
+                "components": [{ "value": { "text": "cpu_temp", "font": "FONT_6X12" } }]
+            }]
+        }"#;
+
+        let path = std::env::temp_dir().join(format!("rustberry-{}-commented.json", std::process::id()));
+        std::fs::write(&path, commented).unwrap();
+        let loaded = PoeDisplay::load_config_from_file(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        let expected: DisplayConfig = serde_json::from_str(plain).unwrap();
+        assert_eq!(serde_json::to_value(loaded.unwrap()).unwrap(), serde_json::to_value(expected).unwrap());
+        // Strict JSON still rejects them
+        assert!(serde_json::from_str::<DisplayConfig>(commented).is_err());
+    }
+}
@@ -7,81 +7,420 @@ use embedded_graphics::{
     prelude::*
 };
 use profont::{PROFONT_12_POINT, PROFONT_9_POINT};
+use display_interface::DisplayError as InterfaceDisplayError;
+use clap::ValueEnum;
 
 use serde::{Deserialize, Serialize};
+use crate::format::NumberFormat;
 
 // New enum for orientation
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
 pub enum Orientation {
     #[serde(rename = "landscape")]
     Landscape,
     #[serde(rename = "portrait")]
     Portrait,
+    // Upside down, for panels mounted the other way round
+    #[serde(rename = "landscape_flipped")]
+    LandscapeFlipped,
+    #[serde(rename = "portrait_flipped")]
+    PortraitFlipped,
 }
 
 impl Orientation {
-    pub fn to_display_rotation(&self) -> DisplayRotation {
+    pub fn to_display_rotation(self) -> DisplayRotation {
         match self {
             Orientation::Portrait => DisplayRotation::Rotate90,
             Orientation::Landscape => DisplayRotation::Rotate0,
+            Orientation::PortraitFlipped => DisplayRotation::Rotate270,
+            Orientation::LandscapeFlipped => DisplayRotation::Rotate180,
         }
     }
+
+    // The same orientation rotated by 180°, which fits the same layout
+    pub fn flipped(&self) -> Orientation {
+        match self {
+            Orientation::Landscape => Orientation::LandscapeFlipped,
+            Orientation::LandscapeFlipped => Orientation::Landscape,
+            Orientation::Portrait => Orientation::PortraitFlipped,
+            Orientation::PortraitFlipped => Orientation::Portrait,
+        }
+    }
+
+    // Whether the layout is taller than wide on the panel
+    pub fn is_portrait(&self) -> bool {
+        matches!(self, Orientation::Portrait | Orientation::PortraitFlipped)
+    }
 }
 
 // The modified DisplayConfig structure - flattened with orientation field
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct DisplayConfig {
     pub orientation: Orientation,
+    // The panel's size in the chosen orientation when left out (or 0)
+    #[serde(default)]
     pub width: i32,
+    #[serde(default)]
     pub height: i32,
     pub elements: Vec<ElementConfig>,
+    // Text the "fan_state" binding resolves to, "ON"/"OFF" when not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fan_state_labels: Option<FanStateLabels>,
+    // Shown instead of readings that are unavailable, "--" when not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub placeholder: Option<String>,
+    // Shown once at startup before the layout
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub splash: Option<SplashConfig>,
+    // What elements showing interface details do while no interface has an address
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_interface: Option<NoInterfaceConfig>,
+    // Decimal and thousands separators of numeric readings, "1234.5" when not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub number_format: Option<NumberFormat>,
+    // "total" (default) shows the net_* error counters since the interface came up, "delta"
+    // the errors since the previous frame
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub net_counters: Option<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
+pub struct NoInterfaceConfig {
+    // "placeholder" (default) shows the placeholder, "text" replaces the element with `text`,
+    // "hide" hides it and "keep_last" keeps showing the last interface that had an address
+    pub mode: String,
+    // "No Network" when not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SplashConfig {
+    pub text: String,
+    // FONT_6X12 when not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub font: Option<String>,
+    // 2000 when not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct FanStateLabels {
+    pub on: String,
+    pub off: String,
+}
+
+#[derive(Deserialize, Serialize)]
 pub struct ElementConfig {
     pub id: String,
     pub position: PositionConfig,
+    // Not needed for line elements
+    #[serde(default)]
     pub components: Vec<ComponentConfig>,
+    // "ltr" (default) or "rtl" to lay components out from the right edge leftward
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direction: Option<String>,
+    // Only draw the element while this rule holds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub visible_when: Option<VisibilityRule>,
+    // Alternate between drawn and blank while visible
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blink: Option<BlinkConfig>,
+    // "interfaces" draws the element once per interface, one line apart, when --ip-mode all is used
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repeat: Option<String>,
+    // Frame drawn around the element
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub border: Option<BorderConfig>,
+    // Makes this a separator line instead of text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<LineConfig>,
+    // Makes this a rectangle instead of text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rect: Option<RectConfig>,
+    // Break text that is wider than the layout into several lines, at spaces where possible
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wrap: Option<bool>,
+    // Elements with a higher z are drawn on top; 0 when not set, list order for equal z
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub z: Option<i32>,
 }
 
-#[derive(Deserialize)]
-#[serde(untagged)]
+#[derive(Deserialize, Serialize)]
+pub struct LineConfig {
+    // "horizontal" or "vertical"
+    pub orientation: String,
+    // Pixels; the full layout width/height when not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub length: Option<i32>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RectConfig {
+    // Pixels; the full layout width/height when not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<i32>,
+    // Filled instead of only outlined
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fill: Option<bool>,
+    // Thickness of the outline, 1 when not set; ignored when filled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stroke_width: Option<u32>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct BorderConfig {
+    pub thickness: u32,
+    // Space between the frame and the text
+    #[serde(default)]
+    pub padding: i32,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct BlinkConfig {
+    pub on_ms: u64,
+    pub off_ms: u64,
+}
+
+impl BlinkConfig {
+    // Whether the element is in the "on" part of its cycle at the given wall-clock time
+    pub fn is_on(&self, now_ms: u128) -> bool {
+        let period = (self.on_ms + self.off_ms) as u128;
+        period == 0 || now_ms % period < self.on_ms as u128
+    }
+}
+
+// Compares a numeric binding against a threshold, e.g. cpu_temp > 70
+#[derive(Deserialize, Serialize)]
+pub struct VisibilityRule {
+    pub value: String,
+    pub op: String,
+    pub threshold: f32,
+}
+
+impl VisibilityRule {
+    // Non-numeric values and unknown operators never match
+    pub fn matches(&self, value: Option<f64>) -> bool {
+        let Some(value) = value.map(|value| value as f32) else {
+            return false;
+        };
+        match self.op.as_str() {
+            ">" => value > self.threshold,
+            ">=" => value >= self.threshold,
+            "<" => value < self.threshold,
+            "<=" => value <= self.threshold,
+            "==" => value == self.threshold,
+            "!=" => value != self.threshold,
+            _ => false,
+        }
+    }
+}
+
+// Read through RawPositionValue, so a position mixing the keys of two forms, e.g. "percent"
+// with "align", is rejected instead of being taken as whichever form matches first
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+#[serde(untagged, from = "RawPositionValue")]
 pub enum PositionValue {
     Number(i32),
     Text(String),
+    // With `anchor_ref`, `anchor` is an offset from the right edge (x) or the bottom (y) of
+    // the element with that id; vertically the element is then placed below it
     Relative {
         align: String,
-        anchor: i32
+        #[serde(default)]
+        anchor: i32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        anchor_ref: Option<String>,
+    },
+    // A coordinate as a percentage of the layout width/height, e.g. { "percent": 50 }
+    Percent {
+        percent: f32
     }
 }
 
 #[derive(Deserialize)]
+#[serde(untagged, expecting = "a number, a keyword, { \"align\": ... } or { \"percent\": ... }")]
+enum RawPositionValue {
+    Number(i32),
+    Text(String),
+    Relative(RawRelativePosition),
+    Percent(RawPercentPosition),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRelativePosition {
+    align: String,
+    #[serde(default)]
+    anchor: i32,
+    anchor_ref: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawPercentPosition {
+    percent: f32,
+}
+
+impl From<RawPositionValue> for PositionValue {
+    fn from(raw: RawPositionValue) -> Self {
+        match raw {
+            RawPositionValue::Number(value) => PositionValue::Number(value),
+            RawPositionValue::Text(value) => PositionValue::Text(value),
+            RawPositionValue::Relative(RawRelativePosition { align, anchor, anchor_ref }) => PositionValue::Relative { align, anchor, anchor_ref },
+            RawPositionValue::Percent(RawPercentPosition { percent }) => PositionValue::Percent { percent },
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
 pub struct PositionConfig {
     pub x: PositionValue,
     pub y: PositionValue,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct ComponentConfig {
     pub value: ValueConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub prefix: Option<PrefixSuffixConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub suffix: Option<PrefixSuffixConfig>,
+    // Whether a change in this value triggers a redraw (default true)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub track_changes: Option<bool>,
+    // Decimal places for numeric readings (default 1); ignored for other values
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub precision: Option<usize>,
+    // "on" (default) or "off" to draw the text inverted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    // Alternative styles for numeric values at or above a threshold
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thresholds: Option<Vec<ThresholdConfig>>,
+    // "upper" or "lower" changes the case of the resolved text, "none" (default) keeps it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transform: Option<String>,
+    // Extra pixels between the characters of the prefix, value and suffix; negative values
+    // tighten the text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub letter_spacing: Option<i32>,
+    // Stack the characters top to bottom instead of left to right, e.g. for labels in
+    // narrow portrait layouts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vertical: Option<bool>,
+    // Shown instead of this component's reading while it is unavailable; the layout's
+    // placeholder when not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub placeholder: Option<String>,
+    // Shown instead of a numeric reading that is exactly zero, to tell it apart from an
+    // unavailable one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zero: Option<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
+pub struct ThresholdConfig {
+    pub above: f64,
+    // Font of the value; the component's font when not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub font: Option<String>,
+    // Color of the prefix, value and suffix; the component's color when not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
 pub struct ValueConfig {
     pub text: String,
     pub font: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct PrefixSuffixConfig {
     pub text: String,
     pub font: String,
 }
 
-// Keep original display type
-pub type Display = Ssd1306<I2CInterface<I2cdev>, DisplaySize128x32, BufferedGraphicsMode<DisplaySize128x32>>;
+// Supported OLED panel sizes
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum PanelSize {
+    #[value(name = "128x32")]
+    Size128x32,
+    #[value(name = "128x64")]
+    Size128x64,
+}
+
+impl PanelSize {
+    // Native (unrotated) panel dimensions
+    pub fn dimensions(&self) -> (i32, i32) {
+        match self {
+            PanelSize::Size128x32 => (128, 32),
+            PanelSize::Size128x64 => (128, 64),
+        }
+    }
+}
+
+pub type Display128x32 = Ssd1306<I2CInterface<I2cdev>, DisplaySize128x32, BufferedGraphicsMode<DisplaySize128x32>>;
+pub type Display128x64 = Ssd1306<I2CInterface<I2cdev>, DisplaySize128x64, BufferedGraphicsMode<DisplaySize128x64>>;
+
+// The panel size is a type parameter of Ssd1306, so wrap each supported size in an enum
+// and forward drawing and flushing to whichever one is in use
+pub enum Display {
+    Size128x32(Box<Display128x32>),
+    Size128x64(Box<Display128x64>),
+}
+
+impl Display {
+    pub fn flush(&mut self) -> Result<(), InterfaceDisplayError> {
+        match self {
+            Display::Size128x32(disp) => disp.flush(),
+            Display::Size128x64(disp) => disp.flush(),
+        }
+    }
+
+    // Switch the panel on or off. Its RAM, and so the picture, is kept while it is off.
+    pub fn set_display_on(&mut self, on: bool) -> Result<(), InterfaceDisplayError> {
+        match self {
+            Display::Size128x32(disp) => disp.set_display_on(on),
+            Display::Size128x64(disp) => disp.set_display_on(on),
+        }
+    }
+}
+
+impl DrawTarget for Display {
+    type Color = BinaryColor;
+    type Error = InterfaceDisplayError;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        match self {
+            Display::Size128x32(disp) => disp.draw_iter(pixels),
+            Display::Size128x64(disp) => disp.draw_iter(pixels),
+        }
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        match self {
+            Display::Size128x32(disp) => disp.clear(color),
+            Display::Size128x64(disp) => disp.clear(color),
+        }
+    }
+}
+
+impl OriginDimensions for Display {
+    fn size(&self) -> Size {
+        match self {
+            Display::Size128x32(disp) => disp.size(),
+            Display::Size128x64(disp) => disp.size(),
+        }
+    }
+}
 
 // Keep all the original font definitions exactly as they were
 pub const PROFONT12: MonoTextStyle<'_, BinaryColor> = MonoTextStyleBuilder::new()
@@ -104,6 +443,18 @@ pub const FONT_5X8: MonoTextStyle<'_, BinaryColor> = MonoTextStyleBuilder::new()
     .text_color(BinaryColor::On)
     .build();
 
+// Very small, for cramped portrait layouts
+pub const FONT_4X6: MonoTextStyle<'_, BinaryColor> = MonoTextStyleBuilder::new()
+    .font(&ascii::FONT_4X6)
+    .text_color(BinaryColor::On)
+    .build();
+
+// Large, for a single prominent reading
+pub const FONT_10X20: MonoTextStyle<'_, BinaryColor> = MonoTextStyleBuilder::new()
+    .font(&ascii::FONT_10X20)
+    .text_color(BinaryColor::On)
+    .build();
+
 pub const GLYPH_MAPPING: StrGlyphMapping = StrGlyphMapping::new(" !\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~°", '?' as usize - ' ' as usize);
 
 pub const PCSENIOR8: MonoFont = MonoFont {
@@ -123,3 +474,41 @@ pub const PCSENIOR8_STYLE: MonoTextStyle<'_, BinaryColor> = MonoTextStyleBuilder
     .font(&PCSENIOR8)
     .text_color(BinaryColor::On)
     .build();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Result<PositionValue, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    #[test]
+    fn percent_position_round_trips() {
+        let position = parse(r#"{ "percent": 50 }"#).unwrap();
+        assert_eq!(position, PositionValue::Percent { percent: 50.0 });
+        let json = serde_json::to_string(&position).unwrap();
+        assert_eq!(json, r#"{"percent":50.0}"#);
+        assert_eq!(parse(&json).unwrap(), position);
+    }
+
+    #[test]
+    fn other_positions_round_trip() {
+        for position in [
+            PositionValue::Number(-1),
+            PositionValue::Text("center".to_string()),
+            PositionValue::Relative { align: "right".to_string(), anchor: 4, anchor_ref: Some("cpu".to_string()) },
+            PositionValue::Relative { align: "left".to_string(), anchor: 0, anchor_ref: None },
+        ] {
+            let json = serde_json::to_string(&position).unwrap();
+            assert_eq!(parse(&json).unwrap(), position, "{}", json);
+        }
+    }
+
+    #[test]
+    fn mixed_position_keys_are_rejected() {
+        assert!(parse(r#"{ "percent": 50, "align": "left" }"#).is_err());
+        assert!(parse(r#"{ "align": "left", "anchor": 2, "percent": 50 }"#).is_err());
+        assert!(parse(r#"{ "align": "left", "offset": 2 }"#).is_err());
+    }
+}
@@ -0,0 +1,85 @@
+use std::time::{Duration, Instant};
+use log::{info, warn};
+
+use crate::display::{DisplayValues, PoeDisplay};
+use crate::display_types::PanelSize;
+use crate::{DISPLAY_ERRORS_BEFORE_RECONNECT, DISPLAY_RECONNECT_DELAY, DISPLAY_RECONNECT_MAX_DELAY};
+
+// A further panel on another I2C address, showing the same readings with its own layout.
+// Unlike the main display it may be missing or fail at any time: it is opened lazily and
+// reconnected with backoff, without holding up the main loop or the other displays.
+pub struct ExtraDisplay {
+    address: u8,
+    config_path: String,
+    panel_size: PanelSize,
+    // None while the panel isn't open
+    display: Option<PoeDisplay>,
+    errors: u32,
+    // When to try opening the panel next; None while it is open
+    next_reconnect: Option<Instant>,
+    reconnect_delay: Duration,
+}
+
+impl ExtraDisplay {
+    pub fn new(address: u8, config_path: &str, panel_size: PanelSize) -> Self {
+        ExtraDisplay {
+            address,
+            config_path: config_path.to_string(),
+            panel_size,
+            display: None,
+            errors: 0,
+            next_reconnect: Some(Instant::now()),
+            reconnect_delay: DISPLAY_RECONNECT_DELAY,
+        }
+    }
+
+    pub fn update(&mut self, values: &DisplayValues) {
+        if let Some(at) = self.next_reconnect {
+            if Instant::now() < at {
+                return;
+            }
+            if !self.open() {
+                return;
+            }
+        }
+        let Some(display) = self.display.as_mut() else { return };
+
+        match display.update_display(values) {
+            Ok(_) => self.errors = 0,
+            Err(e) if e.is_bus_error() => {
+                self.errors += 1;
+                warn!("Failed to update display at 0x{:02x} ({}/{}): {}",
+                      self.address, self.errors, DISPLAY_ERRORS_BEFORE_RECONNECT, e);
+                if self.errors >= DISPLAY_ERRORS_BEFORE_RECONNECT {
+                    warn!("Display at 0x{:02x} not responding after {} attempts, reconnecting", self.address, self.errors);
+                    self.next_reconnect = Some(Instant::now());
+                }
+            },
+            Err(e) => warn!("Failed to update display at 0x{:02x}: {}", self.address, e),
+        }
+    }
+
+    // Open the panel, or reconnect it if it was open before. Whether it worked.
+    fn open(&mut self) -> bool {
+        let result = match self.display.as_mut() {
+            Some(display) => display.reconnect(),
+            None => PoeDisplay::new(&self.config_path, self.panel_size, self.address, 0)
+                .map(|display| self.display = Some(display)),
+        };
+        match result {
+            Ok(()) => {
+                info!("Display at 0x{:02x} initialized with configuration from: {}", self.address, self.config_path);
+                self.next_reconnect = None;
+                self.errors = 0;
+                self.reconnect_delay = DISPLAY_RECONNECT_DELAY;
+                true
+            },
+            Err(e) => {
+                warn!("Failed to open display at 0x{:02x}: {}. Retrying in {:?}", self.address, e, self.reconnect_delay);
+                self.next_reconnect = Some(Instant::now() + self.reconnect_delay);
+                self.reconnect_delay = (self.reconnect_delay * 2).min(DISPLAY_RECONNECT_MAX_DELAY);
+                false
+            }
+        }
+    }
+}
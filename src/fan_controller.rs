@@ -1,26 +1,126 @@
+use clap::ValueEnum;
 use linux_embedded_hal::I2cdev;
+use linux_embedded_hal::i2cdev::core::I2CDevice;
+use linux_embedded_hal::i2cdev::linux::LinuxI2CDevice;
 use pcf857x::{OutputPin, Pcf8574, SlaveAddr};
 use anyhow::{anyhow, Result};
-use log::debug;
+use log::{debug, info};
+use std::thread;
+use std::time::Duration;
 
 
 const I2C_BUS_PATH: &str = "/dev/i2c-1";
+// Pin of the PCF8574 expander that switches the fan on the Waveshare PoE HAT (B)
+pub const DEFAULT_FAN_PIN: u8 = 0;
+// Bus address of the expander, with all address pins low
+const PCF8574_ADDRESS: u16 = 0x20;
+
+// Level of the fan pin that runs the fan. The PoE HAT (B) switches the fan on a low pin.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum FanPolarity {
+    /// The fan runs while the pin is low
+    ActiveLow,
+    /// The fan runs while the pin is high
+    ActiveHigh,
+}
+
+impl FanPolarity {
+    // Pin level (high = true) for the requested fan state
+    fn level(self, running: bool) -> bool {
+        match self {
+            FanPolarity::ActiveLow => !running,
+            FanPolarity::ActiveHigh => running,
+        }
+    }
+}
+
+// One reason to run the fan, with hysteresis: it becomes active at or above `on` and stays
+// active until the reading drops to `off` or below
+#[derive(Clone, Copy, Debug)]
+pub struct FanTrigger {
+    pub on: f32,
+    pub off: f32,
+    pub active: bool,
+}
+
+impl FanTrigger {
+    pub fn new(on: f32, off: f32) -> Self {
+        FanTrigger { on, off, active: false }
+    }
+
+    // Without a reading the trigger keeps its state
+    fn update(&mut self, reading: Option<f32>) {
+        match reading {
+            Some(reading) if self.active && reading <= self.off => self.active = false,
+            Some(reading) if !self.active && reading >= self.on => self.active = true,
+            _ => {},
+        }
+    }
+}
+
+// Decides whether the fan should run: while the temperature is high, or, if configured,
+// while the 1-minute load average is high, so a busy CPU is cooled before it heats up
+pub struct FanTriggers {
+    pub temp: FanTrigger,
+    pub load: Option<FanTrigger>,
+}
+
+impl FanTriggers {
+    pub fn new(temp: FanTrigger, load: Option<FanTrigger>) -> Result<Self> {
+        if temp.off <= 0.0 || temp.on <= 0.0 {
+            return Err(anyhow!("Temperatures must be greater than 0"));
+        }
+        if temp.on <= temp.off {
+            return Err(anyhow!("temp_on must be greater than temp_off"));
+        }
+        if let Some(load) = load {
+            if load.off < 0.0 || load.on <= load.off {
+                return Err(anyhow!("load_on must be greater than load_off, which can't be negative"));
+            }
+        }
+        Ok(FanTriggers { temp, load })
+    }
+
+    // Feed the latest readings and return whether the fan should run
+    pub fn update(&mut self, temp: Option<f32>, load: Option<f32>) -> bool {
+        self.temp.update(temp);
+        if let Some(trigger) = &mut self.load {
+            trigger.update(load);
+        }
+        self.temp.active || self.load.is_some_and(|trigger| trigger.active)
+    }
+
+    // Feed the latest readings and return what to do with a fan that is `is_running`
+    pub fn evaluate(&mut self, temp: Option<f32>, load: Option<f32>, is_running: bool) -> FanAction {
+        match (self.update(temp, load), is_running) {
+            (true, false) => FanAction::TurnOn,
+            (false, true) => FanAction::TurnOff,
+            _ => FanAction::NoChange,
+        }
+    }
+}
+
+// What the fan controller should do after a new reading
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FanAction {
+    NoChange,
+    TurnOn,
+    TurnOff,
+}
 
 pub struct FanController {
     expander: Pcf8574<I2cdev>,
+    pin: u8,
+    polarity: FanPolarity,
     pub is_running: bool,
-    pub temp_on: f32,
-    pub temp_off: f32,
+    pub triggers: FanTriggers,
 }
 
 impl FanController {
-    pub fn new(temp_on: f32, temp_off: f32) -> Result<Self> {
+    pub fn new(triggers: FanTriggers, pin: u8, polarity: FanPolarity) -> Result<Self> {
         debug!("Initializing FanController");
-        if temp_off <= 0.0 || temp_on <= 0.0 {
-            return Err(anyhow!("Temperatures must be greater than 0"));
-        }
-        if temp_on <= temp_off {
-            return Err(anyhow!("temp_on must be greater than temp_off"));
+        if pin > 7 {
+            return Err(anyhow!("Fan pin must be between 0 and 7 (P0-P7 of the PCF8574), got {}", pin));
         }
         
         let i2c = I2cdev::new(I2C_BUS_PATH)?;
@@ -30,25 +130,187 @@ impl FanController {
         
         Ok(FanController {
             expander,
+            pin,
+            polarity,
             is_running: false,
-            temp_off,
-            temp_on,
+            triggers,
         })
     }
 
+    // Decide from the latest readings whether the fan has to be switched. Only the triggers
+    // are updated; the caller applies the action with fan_on()/fan_off().
+    pub fn evaluate(&mut self, temp: Option<f32>, load: Option<f32>) -> FanAction {
+        self.triggers.evaluate(temp, load, self.is_running)
+    }
+
+    // Take over a fan state found on the pin instead of switching the fan, with the
+    // temperature trigger matching it so the hysteresis carries on from there
+    pub fn assume_running(&mut self, running: bool) {
+        self.is_running = running;
+        self.triggers.temp.active = running;
+    }
+
     pub fn fan_on(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        debug!("Sending fan on signal [p0: low]");
-        let mut parts = self.expander.split();
-        parts.p0.set_low().unwrap();
+        debug!("Sending fan on signal [p{}: {:?}]", self.pin, self.polarity);
+        self.set_pin(self.polarity.level(true))?;
         self.is_running = true;
         Ok(())
     }
 
     pub fn fan_off(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        debug!("Sending fan off signal [p0: high]");
-        let mut parts = self.expander.split();
-        parts.p0.set_high().unwrap();
+        debug!("Sending fan off signal [p{}: {:?}]", self.pin, self.polarity);
+        self.set_pin(self.polarity.level(false))?;
         self.is_running = false;
         Ok(())
     }
-}
\ No newline at end of file
+
+    // Run the fan for `duration` so it can be heard or seen working, then stop it
+    pub fn self_test(&mut self, duration: Duration) -> Result<(), Box<dyn std::error::Error>> {
+        info!("Fan self-test: running the fan for {:?}", duration);
+        self.fan_on()?;
+        thread::sleep(duration);
+        self.fan_off()
+    }
+
+    fn set_pin(&mut self, high: bool) -> Result<(), Box<dyn std::error::Error>> {
+        let mut parts = self.expander.split();
+        let result = match self.pin {
+            0 => set_level(&mut parts.p0, high),
+            1 => set_level(&mut parts.p1, high),
+            2 => set_level(&mut parts.p2, high),
+            3 => set_level(&mut parts.p3, high),
+            4 => set_level(&mut parts.p4, high),
+            5 => set_level(&mut parts.p5, high),
+            6 => set_level(&mut parts.p6, high),
+            _ => set_level(&mut parts.p7, high),
+        };
+        result.map_err(|e| format!("Cannot set fan pin P{}: {:?}", self.pin, e).into())
+    }
+}
+
+fn set_level<P: OutputPin>(pin: &mut P, high: bool) -> Result<(), P::Error> {
+    if high {
+        pin.set_high()
+    } else {
+        pin.set_low()
+    }
+}
+
+// Whether the fan is running, read back from the expander without changing any pin. Used by
+// other processes than the one controlling the fan, e.g. a health check.
+pub fn read_fan_running(pin: u8, polarity: FanPolarity) -> Result<bool> {
+    if pin > 7 {
+        return Err(anyhow!("Fan pin must be between 0 and 7 (P0-P7 of the PCF8574), got {}", pin));
+    }
+    let mut device = LinuxI2CDevice::new(I2C_BUS_PATH, PCF8574_ADDRESS)?;
+    let mut port = [0u8];
+    device.read(&mut port)?;
+    let high = port[0] & (1 << pin) != 0;
+    Ok(high == polarity.level(true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustberry_poe_monitor::metrics::{IpSettings, MetricsSource, SystemMetricsSource};
+
+    #[test]
+    fn polarity_sets_the_pin_level() {
+        assert!(!FanPolarity::ActiveLow.level(true));
+        assert!(FanPolarity::ActiveLow.level(false));
+        assert!(FanPolarity::ActiveHigh.level(true));
+        assert!(!FanPolarity::ActiveHigh.level(false));
+    }
+
+    #[test]
+    fn temperature_trigger_has_hysteresis() {
+        let mut triggers = FanTriggers::new(FanTrigger::new(60.0, 50.0), None).unwrap();
+        let mut update = |temp: f32| triggers.update(Some(temp), None);
+
+        assert!(!update(45.0));
+        assert!(!update(59.9));
+        // Crossing temp_on
+        assert!(update(60.0));
+        // Held between temp_off and temp_on
+        assert!(update(55.0));
+        assert!(update(50.1));
+        // Crossing temp_off
+        assert!(!update(50.0));
+        // Held off again until temp_on
+        assert!(!update(55.0));
+        assert!(!update(59.9));
+        assert!(update(61.0));
+    }
+
+    #[test]
+    fn either_trigger_runs_the_fan() {
+        let mut triggers = FanTriggers::new(FanTrigger::new(60.0, 50.0), Some(FanTrigger::new(3.0, 2.0))).unwrap();
+
+        assert!(!triggers.update(Some(45.0), Some(1.0)));
+        // High load before the temperature rises
+        assert!(triggers.update(Some(45.0), Some(3.5)));
+        assert!(triggers.update(Some(45.0), Some(2.5)));
+        assert!(!triggers.update(Some(45.0), Some(2.0)));
+        // Both active: runs until both have dropped to their off values
+        assert!(triggers.update(Some(65.0), Some(3.0)));
+        assert!(triggers.update(Some(49.0), Some(2.5)));
+        assert!(!triggers.update(Some(49.0), Some(1.5)));
+    }
+
+    #[test]
+    fn triggers_reject_inverted_thresholds() {
+        assert!(FanTriggers::new(FanTrigger::new(50.0, 60.0), None).is_err());
+        assert!(FanTriggers::new(FanTrigger::new(60.0, 60.0), None).is_err());
+        assert!(FanTriggers::new(FanTrigger::new(60.0, 0.0), None).is_err());
+        assert!(FanTriggers::new(FanTrigger::new(60.0, 50.0), Some(FanTrigger::new(1.0, 2.0))).is_err());
+        assert!(FanTriggers::new(FanTrigger::new(60.0, 50.0), Some(FanTrigger::new(2.0, -1.0))).is_err());
+    }
+
+    #[test]
+    fn missing_temperature_keeps_the_fan_as_it_is() {
+        let mut triggers = FanTriggers::new(FanTrigger::new(60.0, 50.0), None).unwrap();
+
+        assert_eq!(triggers.evaluate(None, None, false), FanAction::NoChange);
+        assert_eq!(triggers.evaluate(Some(65.0), None, false), FanAction::TurnOn);
+        assert_eq!(triggers.evaluate(None, None, true), FanAction::NoChange);
+        assert_eq!(triggers.evaluate(Some(45.0), None, true), FanAction::TurnOff);
+        assert_eq!(triggers.evaluate(None, None, false), FanAction::NoChange);
+    }
+
+    #[test]
+    fn load_and_temperature_are_evaluated_together() {
+        let mut triggers = FanTriggers::new(FanTrigger::new(60.0, 50.0), Some(FanTrigger::new(3.0, 2.0))).unwrap();
+
+        assert_eq!(triggers.evaluate(Some(45.0), Some(1.0), false), FanAction::NoChange);
+        assert_eq!(triggers.evaluate(Some(45.0), Some(3.0), false), FanAction::TurnOn);
+        // The temperature rises while the load is still high
+        assert_eq!(triggers.evaluate(Some(62.0), Some(2.5), true), FanAction::NoChange);
+        // The load drops, the temperature keeps the fan running
+        assert_eq!(triggers.evaluate(Some(55.0), Some(1.0), true), FanAction::NoChange);
+        assert_eq!(triggers.evaluate(Some(50.0), Some(1.0), true), FanAction::TurnOff);
+        // A missing load reading leaves the load trigger as it was
+        assert_eq!(triggers.evaluate(Some(45.0), None, false), FanAction::NoChange);
+        assert_eq!(triggers.evaluate(None, Some(4.0), false), FanAction::TurnOn);
+    }
+
+    #[test]
+    fn temp_offset_reaches_the_fan_decision() {
+        let zone = std::env::temp_dir().join(format!("rustberry-{}-zone-fan", std::process::id()));
+        let zone = zone.to_string_lossy().into_owned();
+        let decide = |millidegrees: &str, offset: f32| {
+            std::fs::write(&zone, millidegrees).unwrap();
+            let mut source = SystemMetricsSource::new(vec![zone.clone()], IpSettings::default()).with_temp_offset(offset);
+            let mut triggers = FanTriggers::new(FanTrigger::new(60.0, 50.0), None).unwrap();
+            triggers.evaluate(source.cpu_temperature(), None, false)
+        };
+
+        // 1°C below temp_on, read 2°C higher
+        assert_eq!(decide("59000\n", 0.0), FanAction::NoChange);
+        assert_eq!(decide("59000\n", 2.0), FanAction::TurnOn);
+        // 1°C above temp_on, read 2°C lower
+        assert_eq!(decide("61000\n", 0.0), FanAction::TurnOn);
+        assert_eq!(decide("61000\n", -2.0), FanAction::NoChange);
+
+        std::fs::remove_file(&zone).unwrap();
+    }
+}
@@ -0,0 +1,176 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum TempUnit {
+    Celsius,
+    Fahrenheit,
+}
+
+// Fixed number of decimals, with halves rounded away from zero (so 2.25 -> "2.3"), unlike
+// `{:.1}` which rounds exact halves to even
+pub fn format_number(value: f64, precision: usize) -> String {
+    let factor = 10f64.powi(precision as i32);
+    format!("{:.*}", precision, (value * factor).round() / factor)
+}
+
+// Separators for numbers on the display, e.g. `decimal: ','` and `thousands: '.'` for
+// "1.234,5". The default is a period and no grouping.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct NumberFormat {
+    #[serde(default = "default_decimal")]
+    pub decimal: char,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thousands: Option<char>,
+}
+
+fn default_decimal() -> char {
+    '.'
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        NumberFormat { decimal: default_decimal(), thousands: None }
+    }
+}
+
+impl NumberFormat {
+    // Rewrite a number formatted with a period, such as "-1234.5" or "2.5GB", with these
+    // separators. Text around the number (a sign or unit) is kept as is.
+    pub fn localize(&self, number: &str) -> String {
+        let (integer, fraction) = match number.split_once('.') {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (number, None),
+        };
+        let digits_start = integer.find(|c: char| c.is_ascii_digit()).unwrap_or(integer.len());
+        let (sign, digits) = integer.split_at(digits_start);
+        let digits_end = digits.find(|c: char| !c.is_ascii_digit()).unwrap_or(digits.len());
+        let (digits, rest) = digits.split_at(digits_end);
+
+        let mut text = sign.to_string();
+        for (index, digit) in digits.chars().enumerate() {
+            if let Some(separator) = self.thousands.filter(|_| index > 0 && (digits.len() - index) % 3 == 0) {
+                text.push(separator);
+            }
+            text.push(digit);
+        }
+        text.push_str(rest);
+        if let Some(fraction) = fraction {
+            text.push(self.decimal);
+            text.push_str(fraction);
+        }
+        text
+    }
+
+    // Read back a number written with these separators, e.g. to compare it with a threshold
+    pub fn parse(&self, text: &str) -> Option<f64> {
+        let text: String = text.trim().chars()
+            .filter(|&c| Some(c) != self.thousands)
+            .map(|c| if c == self.decimal { '.' } else { c })
+            .collect();
+        text.parse().ok()
+    }
+}
+
+fn format_one_decimal(value: f64) -> String {
+    format_number(value, 1)
+}
+
+pub fn format_percent(value: f64) -> String {
+    format_one_decimal(value)
+}
+
+// Temperatures are measured in Celsius and converted for display
+pub fn convert_temp(celsius: f32, unit: TempUnit) -> f64 {
+    match unit {
+        TempUnit::Celsius => celsius as f64,
+        TempUnit::Fahrenheit => celsius as f64 * 9.0 / 5.0 + 32.0,
+    }
+}
+
+pub fn format_temp(celsius: f32, unit: TempUnit) -> String {
+    format_one_decimal(convert_temp(celsius, unit))
+}
+
+// Decimal units: whole bytes below 1000, then one decimal, e.g. "999B", "1.0KB", "2.5GB"
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{}B", bytes);
+    }
+    let mut value = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    // Move up a unit if rounding would print "1000.0"
+    while (value * 10.0).round() >= 10000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    format!("{}{}", format_one_decimal(value), UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_bytes_switches_units_at_1000() {
+        assert_eq!(format_bytes(0), "0B");
+        assert_eq!(format_bytes(999), "999B");
+        assert_eq!(format_bytes(1000), "1.0KB");
+        assert_eq!(format_bytes(1049), "1.0KB");
+        assert_eq!(format_bytes(1050), "1.1KB");
+        assert_eq!(format_bytes(999_949), "999.9KB");
+        // Would round to "1000.0KB"
+        assert_eq!(format_bytes(999_950), "1.0MB");
+        assert_eq!(format_bytes(1_000_000), "1.0MB");
+        assert_eq!(format_bytes(2_500_000_000), "2.5GB");
+        // Nothing above TB
+        assert_eq!(format_bytes(5_000_000_000_000_000), "5000.0TB");
+    }
+
+    #[test]
+    fn format_number_rounds_halves_away_from_zero() {
+        assert_eq!(format_number(0.05, 1), "0.1");
+        assert_eq!(format_number(0.04, 1), "0.0");
+        assert_eq!(format_number(2.25, 1), "2.3");
+        assert_eq!(format_number(-2.25, 1), "-2.3");
+        assert_eq!(format_number(9.95, 1), "10.0");
+        assert_eq!(format_number(0.5, 0), "1");
+    }
+
+    #[test]
+    fn format_temp_converts_to_fahrenheit() {
+        assert_eq!(format_temp(48.25, TempUnit::Celsius), "48.3");
+        assert_eq!(format_temp(100.0, TempUnit::Fahrenheit), "212.0");
+        assert_eq!(format_temp(-40.0, TempUnit::Fahrenheit), "-40.0");
+    }
+
+    #[test]
+    fn localized_numbers_parse_back() {
+        let european = NumberFormat { decimal: ',', thousands: Some('.') };
+        let cases = [("0.5", "0,5"), ("48.3", "48,3"), ("1234.5", "1.234,5"), ("1234567", "1.234.567"),
+                     ("-1234.5", "-1.234,5"), ("-0.25", "-0,25"), ("123", "123"), ("-999.9", "-999,9")];
+        for (number, localized) in cases {
+            assert_eq!(european.localize(number), localized);
+            assert_eq!(european.parse(localized), number.parse().ok(), "{}", localized);
+        }
+
+        let grouped = NumberFormat { decimal: '.', thousands: Some(',') };
+        assert_eq!(grouped.localize("-1234567.89"), "-1,234,567.89");
+        assert_eq!(grouped.parse("-1,234,567.89"), Some(-1234567.89));
+
+        let plain = NumberFormat::default();
+        assert_eq!(plain.localize("-1234.5"), "-1234.5");
+        assert_eq!(plain.parse(" -1234.5 "), Some(-1234.5));
+    }
+
+    #[test]
+    fn localize_keeps_units_and_parse_rejects_them() {
+        let european = NumberFormat { decimal: ',', thousands: Some('.') };
+        assert_eq!(european.localize("2.5GB"), "2,5GB");
+        assert_eq!(european.localize("1500MB"), "1.500MB");
+        assert_eq!(european.parse("2,5GB"), None);
+        // A period is a thousands separator here, not a decimal point
+        assert_eq!(european.parse("1.5"), Some(15.0));
+    }
+}
@@ -0,0 +1,114 @@
+use std::convert::Infallible;
+use std::fs::File;
+use std::io::BufWriter;
+use embedded_graphics::{
+    pixelcolor::BinaryColor,
+    prelude::*,
+};
+
+// Off-screen 1-bit frame used to compose a full frame before anything is sent to the panel,
+// so the result can be compared with the previously shown frame
+#[derive(Clone, PartialEq)]
+pub struct FrameBuffer {
+    size: Size,
+    pixels: Vec<bool>,
+    // Lit pixels that fell outside the frame, e.g. of an element that doesn't fit
+    clipped: usize,
+}
+
+impl FrameBuffer {
+    pub fn new(size: Size) -> Self {
+        FrameBuffer {
+            size,
+            pixels: vec![false; (size.width * size.height) as usize],
+            clipped: 0,
+        }
+    }
+
+    pub fn clipped(&self) -> usize {
+        self.clipped
+    }
+
+    // All pixels of this frame, regardless of what is currently on the panel
+    pub fn pixels(&self) -> impl Iterator<Item = Pixel<BinaryColor>> + '_ {
+        self.pixels.iter().enumerate().map(move |(i, on)| Pixel(self.point(i), BinaryColor::from(*on)))
+    }
+
+    // Pixels of this frame that differ from `previous`
+    pub fn changed_pixels<'a>(&'a self, previous: &'a FrameBuffer) -> impl Iterator<Item = Pixel<BinaryColor>> + 'a {
+        self.pixels
+            .iter()
+            .zip(previous.pixels.iter())
+            .enumerate()
+            .filter(|(_, (new, old))| new != old)
+            .map(move |(i, (new, _))| Pixel(self.point(i), BinaryColor::from(*new)))
+    }
+
+    // Save the frame as a grayscale PNG, lit pixels white
+    pub fn write_png(&self, path: &str) -> std::io::Result<()> {
+        let file = File::create(path)?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.size.width, self.size.height);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let data: Vec<u8> = self.pixels.iter().map(|on| if *on { 255 } else { 0 }).collect();
+        encoder.write_header()?.write_image_data(&data)?;
+        Ok(())
+    }
+
+    // The frame as plain text, one line per row with "#" for lit and "." for dark pixels,
+    // so rendered layouts can be kept as snapshots and compared with a plain diff
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for row in self.pixels.chunks(self.size.width as usize) {
+            text.extend(row.iter().map(|on| if *on { '#' } else { '.' }));
+            text.push('\n');
+        }
+        text
+    }
+
+    pub fn write_text(&self, path: &str) -> std::io::Result<()> {
+        std::fs::write(path, self.to_text())
+    }
+
+    fn index(&self, point: Point) -> Option<usize> {
+        if point.x < 0 || point.y < 0 || point.x >= self.size.width as i32 || point.y >= self.size.height as i32 {
+            return None;
+        }
+        Some(point.y as usize * self.size.width as usize + point.x as usize)
+    }
+
+    fn point(&self, index: usize) -> Point {
+        let width = self.size.width as usize;
+        Point::new((index % width) as i32, (index / width) as i32)
+    }
+}
+
+impl DrawTarget for FrameBuffer {
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            match self.index(point) {
+                Some(i) => self.pixels[i] = color.is_on(),
+                None if color.is_on() => self.clipped += 1,
+                None => {},
+            }
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.pixels.fill(color.is_on());
+        Ok(())
+    }
+}
+
+impl OriginDimensions for FrameBuffer {
+    fn size(&self) -> Size {
+        self.size
+    }
+}
@@ -0,0 +1,100 @@
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use sysinfo::System;
+use tiny_http::{Header, Request, Response, Server};
+
+// Latest readings, written by the main loop and read by the HTTP thread.
+// Unavailable readings are None: null in /status and left out of /metrics.
+#[derive(Clone, Default, Serialize)]
+pub struct MetricsSnapshot {
+    pub cpu_temperature: Option<f32>,
+    pub cpu_usage: f32,
+    pub ram_usage: Option<f64>,
+    pub disk_usage: Option<f64>,
+    pub fan_running: bool,
+    pub fan_duty_percent: f32,
+    pub interface: String,
+    pub ip_address: String,
+}
+
+// Body of the /status endpoint
+#[derive(Serialize)]
+struct Status<'a> {
+    timestamp: u64,
+    uptime_secs: u64,
+    system_uptime_secs: u64,
+    #[serde(flatten)]
+    metrics: &'a MetricsSnapshot,
+}
+
+pub type SharedMetrics = Arc<Mutex<MetricsSnapshot>>;
+
+// Serve the shared metrics on a background thread
+pub fn start_server(port: u16, metrics: SharedMetrics) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let server = Server::http(("0.0.0.0", port))?;
+    info!("Serving metrics on port {}", port);
+    let started = Instant::now();
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let snapshot = metrics.lock().unwrap().clone();
+            if let Err(e) = handle_request(request, &snapshot, started) {
+                warn!("Failed to answer HTTP request: {}", e);
+            }
+        }
+        error!("Metrics server stopped");
+    });
+    Ok(())
+}
+
+fn handle_request(request: Request, snapshot: &MetricsSnapshot, started: Instant) -> std::io::Result<()> {
+    debug!("HTTP {} {}", request.method(), request.url());
+    match request.url() {
+        "/metrics" => {
+            let header = Header::from_bytes("Content-Type", "text/plain; version=0.0.4").unwrap();
+            request.respond(Response::from_string(prometheus_text(snapshot)).with_header(header))
+        },
+        "/status" => {
+            let header = Header::from_bytes("Content-Type", "application/json").unwrap();
+            request.respond(Response::from_string(status_json(snapshot, started)).with_header(header))
+        },
+        _ => request.respond(Response::from_string("Not Found").with_status_code(404)),
+    }
+}
+
+// Render the snapshot in the Prometheus text exposition format
+fn prometheus_text(snapshot: &MetricsSnapshot) -> String {
+    let gauges: [(&str, &str, Option<f64>); 6] = [
+        ("cpu_temperature_celsius", "CPU temperature in degrees Celsius", snapshot.cpu_temperature.map(f64::from)),
+        ("cpu_usage_percent", "Global CPU usage in percent", Some(snapshot.cpu_usage as f64)),
+        ("ram_usage_percent", "Used RAM in percent", snapshot.ram_usage),
+        ("disk_usage_percent", "Used disk space in percent", snapshot.disk_usage),
+        ("fan_running", "Whether the fan is running (1) or stopped (0)", Some(if snapshot.fan_running { 1.0 } else { 0.0 })),
+        ("fan_duty_percent", "Fan speed in percent of full speed", Some(snapshot.fan_duty_percent as f64)),
+    ];
+
+    let mut text = String::new();
+    for (name, help, value) in gauges {
+        let Some(value) = value else {
+            continue;
+        };
+        let _ = writeln!(text, "# HELP {} {}", name, help);
+        let _ = writeln!(text, "# TYPE {} gauge", name);
+        let _ = writeln!(text, "{} {}", name, value);
+    }
+    text
+}
+
+fn status_json(snapshot: &MetricsSnapshot, started: Instant) -> String {
+    let status = Status {
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        uptime_secs: started.elapsed().as_secs(),
+        system_uptime_secs: System::uptime(),
+        metrics: snapshot,
+    };
+    serde_json::to_string(&status).unwrap_or_else(|e| format!("{{\"error\":\"{}\"}}", e))
+}
@@ -0,0 +1,14 @@
+// Metric collectors and the display renderer used by the monitor, usable on their own
+// from other crates and from the integration tests
+pub mod bindings;
+pub mod commands;
+pub mod default_config;
+pub mod demo;
+pub mod display;
+pub mod display_types;
+pub mod format;
+pub mod framebuffer;
+pub mod metrics;
+pub mod throttle;
+
+pub use metrics::{collect_metrics, Metrics, MetricsSource, SystemMetricsSource};
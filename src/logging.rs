@@ -0,0 +1,63 @@
+use clap::ValueEnum;
+use env_logger::{Builder, Env};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum LogTarget {
+    /// Human readable lines on stderr
+    Stderr,
+    /// Structured records sent to the systemd journal
+    #[cfg(feature = "journald")]
+    Journald,
+}
+
+// Set up the global logger. Both targets honour RUST_LOG, which defaults to "info", or to
+// "warn" in quiet mode.
+//
+// Level guidelines: info is for notable, infrequent events (startup, IP changes, fan
+// transitions, config loading); anything that happens every loop or every refresh is
+// debug, and per-line/per-call detail is trace.
+pub fn init(target: LogTarget, quiet: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let env = Env::default().default_filter_or(if quiet { "warn" } else { "info" });
+
+    match target {
+        LogTarget::Stderr => Builder::from_env(env).init(),
+        #[cfg(feature = "journald")]
+        LogTarget::Journald => {
+            let filter = Builder::from_env(env).build();
+            log::set_max_level(filter.filter());
+            let journal = systemd_journal_logger::JournalLog::new()
+                .map_err(|e| format!("Cannot connect to the systemd journal: {}", e))?;
+            log::set_boxed_logger(Box::new(journald::FilteredJournal { filter, journal }))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "journald")]
+mod journald {
+    use log::{Log, Metadata, Record};
+    use systemd_journal_logger::JournalLog;
+
+    // Applies the env_logger filter before handing records (including their key-value
+    // fields) to the journal
+    pub struct FilteredJournal {
+        pub filter: env_logger::Logger,
+        pub journal: JournalLog,
+    }
+
+    impl Log for FilteredJournal {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            self.filter.enabled(metadata)
+        }
+
+        fn log(&self, record: &Record) {
+            if self.filter.matches(record) {
+                self.journal.log(record);
+            }
+        }
+
+        fn flush(&self) {
+            self.journal.flush();
+        }
+    }
+}
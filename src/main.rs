@@ -1,48 +1,289 @@
 use std::error::Error;
-use std::fs;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 use std::time::{Duration, Instant};
-use sysinfo::{System, Disks, RefreshKind, CpuRefreshKind, MemoryRefreshKind};
+use sysinfo::System;
 use log::{info, debug, trace, error, warn};
-use clap::Parser;
-use env_logger::{Builder, Env};
+use clap::{Parser, ValueEnum};
 
-use lazy_static::lazy_static;
-use std::process::Command;
-use std::sync::Mutex;
+use rustberry_poe_monitor::metrics::{self, get_cpu_temperature, power_source_label, ByteUsage, HatInfo, InterfaceIp, IpSettings, MetricsSource, ProcessUsage, SystemMetricsSource};
+use rustberry_poe_monitor::format::{convert_temp, format_percent, format_temp, TempUnit};
+use rustberry_poe_monitor::throttle::ThrottleStatus;
+use rustberry_poe_monitor::demo::DemoMetrics;
+use rustberry_poe_monitor::{bindings, commands, default_config, display, display_types, framebuffer};
 
 mod fan_controller;
-use fan_controller::FanController;
+use fan_controller::{FanAction, FanController, FanPolarity, FanTrigger, FanTriggers};
 
-mod display;
-use display::PoeDisplay;
+use display::{DisplayValues, PoeDisplay};
+mod extra_display;
+use extra_display::ExtraDisplay;
 
-mod display_types;
-mod default_config;
+use display_types::PanelSize;
+
+mod logging;
+use logging::LogTarget;
+
+mod temperature;
+use temperature::TemperatureAverage;
+
+use framebuffer::FrameBuffer;
+mod csv_log;
+mod state;
+use state::SavedState;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+#[cfg(feature = "http")]
+mod http;
+#[cfg(feature = "webhook")]
+mod webhook;
+#[cfg(feature = "accelerometer")]
+mod accelerometer;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    #[clap(long, default_value_t = 60.0)]
+    #[clap(long, env = "TEMP_ON", default_value_t = 60.0)]
     temp_on: f32,
 
-    #[clap(long, default_value_t = 50.0)]
+    #[clap(long, env = "TEMP_OFF", default_value_t = 50.0)]
     temp_off: f32,
 
-    #[arg(long, default_value = "/etc/rustberry-poe-monitor/portrait.json")]
+    /// Also run the fan while the 1-minute load average is at or above this, before the
+    /// temperature rises (off by default)
+    #[arg(long)]
+    load_on: Option<f32>,
+
+    /// Load average at or below which the load no longer keeps the fan running
+    /// [default: 75% of --load-on]
+    #[arg(long, requires = "load_on")]
+    load_off: Option<f32>,
+
+    /// Degrees Celsius added to every temperature reading, to calibrate the sensor (e.g. -2.5
+    /// if it reads 2.5°C too high). Applies to the display, fan control, health check and logs.
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    temp_offset: f32,
+
+    /// Temperature at which --healthcheck reports a failure
+    #[arg(long, default_value_t = 80.0)]
+    temp_critical: f32,
+
+    /// PCF8574 expander pin (0-7) that switches the fan
+    #[arg(long, default_value_t = fan_controller::DEFAULT_FAN_PIN)]
+    fan_pin: u8,
+
+    /// Pin level that runs the fan
+    #[arg(long, value_enum, default_value = "active-low")]
+    fan_polarity: FanPolarity,
+
+    /// Milliseconds to run the fan at startup as a self-test (0 skips the test)
+    #[arg(long, default_value_t = 2000)]
+    fan_test_ms: u64,
+
+    /// File reporting the power source ("1" = PoE, "0" = other), e.g. a GPIO value in sysfs
+    #[arg(long, value_name = "PATH")]
+    poe_status_path: Option<String>,
+
+    /// Unit of the temperature shown on the display (fan thresholds, logs and exports stay in Celsius)
+    #[arg(long, value_enum, default_value = "celsius")]
+    temp_unit: TempUnit,
+
+    /// Thermal zones to read the CPU temperature from (/sys/class/thermal/thermal_zone<n>/temp),
+    /// comma separated; the hottest one is used
+    #[arg(long, value_delimiter = ',', default_value = "0")]
+    thermal_zone: Vec<u32>,
+
+    /// Number of temperature readings averaged for the display and fan control (1 disables smoothing)
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+    temp_samples: u64,
+
+    #[arg(long, env = "RUSTBERRY_CONFIG", default_value = "/etc/rustberry-poe-monitor/portrait.json")]
     config: String,
-}
 
+    /// OLED panel size
+    #[arg(long, value_enum, default_value = "128x32")]
+    display_size: PanelSize,
+
+    /// How often to retry opening the display at startup, with exponential backoff from 500ms
+    #[arg(long, default_value_t = 5)]
+    display_init_retries: u32,
+
+    /// I2C address of the display, e.g. 0x3d for a panel with its address pin high
+    #[arg(long, value_name = "ADDRESS", default_value = "0x3c", value_parser = parse_i2c_address)]
+    display_address: u8,
+
+    /// Another display of the same size, at ADDRESS and showing the layout in PATH
+    /// (repeatable). It is retried in the background if it is missing or fails.
+    #[arg(long, value_name = "ADDRESS=PATH", value_parser = parse_extra_display)]
+    extra_display: Vec<(u8, String)>,
+
+    /// Main loop interval in milliseconds: how often metrics are sampled and the fan is controlled
+    #[arg(long, default_value_t = 500, value_parser = clap::value_parser!(u64).range(1..))]
+    interval_ms: u64,
+
+    /// Redraw the display at most this often, in milliseconds (default: every loop)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    render_interval_ms: Option<u64>,
+
+    /// While the shown content stays the same, redraw less and less often, up to this
+    /// interval in milliseconds; any change returns to the render interval
+    #[arg(long, value_name = "MS", value_parser = clap::value_parser!(u64).range(1..))]
+    idle_render_interval_ms: Option<u64>,
+
+    /// Log a warning when disk usage rises to this percentage, and again once it drops below
+    #[arg(long, value_name = "PERCENT")]
+    disk_warn_percent: Option<f64>,
+
+    #[cfg(feature = "mqtt")]
+    #[command(flatten)]
+    mqtt: mqtt::MqttArgs,
+
+    /// Serve Prometheus metrics on this port
+    #[cfg(feature = "http")]
+    #[arg(long)]
+    metrics_port: Option<u16>,
+
+    /// URL that receives a JSON POST when the fan turns on or off
+    #[cfg(feature = "webhook")]
+    #[arg(long)]
+    webhook_url: Option<String>,
+
+    /// Turn the display to match an ADXL345 accelerometer on the I2C bus
+    #[cfg(feature = "accelerometer")]
+    #[arg(long)]
+    accelerometer: bool,
+
+    /// Layout shown while the accelerometer reports the device turned by 90° from the
+    /// orientation of the --config layout. Without it the display only turns by 180°.
+    #[cfg(feature = "accelerometer")]
+    #[arg(long, value_name = "PATH", requires = "accelerometer")]
+    rotated_config: Option<String>,
+
+    /// Let cmd(<command>) bindings in the layout run shell commands. Only use this with
+    /// layout files that no one else can edit.
+    #[arg(long)]
+    allow_commands: bool,
+
+    /// Append metrics to this CSV file
+    #[arg(long, value_name = "PATH")]
+    log_csv: Option<String>,
+
+    /// Seconds between CSV rows
+    #[arg(long, default_value_t = 60)]
+    log_csv_interval_secs: u64,
+
+    /// Save the last shown readings to this file on shutdown and show them again right
+    /// after the next start, until fresh readings are in
+    #[arg(long, value_name = "PATH")]
+    state_file: Option<String>,
+
+    /// Where log records go
+    #[arg(long, value_enum, default_value = "stderr")]
+    log_target: LogTarget,
+
+    /// Only log warnings and errors unless RUST_LOG says otherwise
+    #[arg(long, short)]
+    quiet: bool,
 
-lazy_static! {
-    static ref IP_ADDRESSES: Mutex<Vec<(String, String, [u8; 4])>> = Mutex::new(Vec::new());
-    static ref CURRENT_INDEX: Mutex<usize> = Mutex::new(0);
-    static ref LAST_IP_REFRESH: Mutex<Instant> = Mutex::new(Instant::now());
+    /// Write the default display config as JSON to the given path and exit
+    #[arg(long, value_name = "PATH")]
+    init_config: Option<String>,
+
+    /// Print the effective display config (after any fallback to the default) as JSON and exit
+    #[arg(long)]
+    print_config: bool,
+
+    /// Load the --config layout, render it with the sample readings of --render-to and exit
+    /// non-zero if it can't be loaded or rendered, or draws outside the panel
+    #[arg(long)]
+    check_config: bool,
+
+    /// Sample the metrics, render a single frame and exit; the exit status tells whether
+    /// the display was updated
+    #[arg(long)]
+    once: bool,
+
+    /// Check the temperature and fan once, print a one-line status and exit non-zero if the
+    /// temperature is critical or the fan should be running but isn't
+    #[arg(long)]
+    healthcheck: bool,
+
+    /// Print the readings a component value can show and exit
+    #[arg(long)]
+    list_bindings: bool,
+
+    /// Render one frame with sample readings to the given PNG file (or text, for a .txt
+    /// path) and exit, without touching the display or the fan
+    #[arg(long, value_name = "PATH")]
+    render_to: Option<String>,
+
+    /// Show synthetic, changing readings instead of the system's. With --render-to, the
+    /// file is rewritten every loop instead of exiting, and no hardware is touched.
+    #[arg(long)]
+    demo: bool,
+
+    /// Wait up to this many seconds at startup for an interface to get an address before the
+    /// first frame is drawn, so the display doesn't start out without one (0: don't wait)
+    #[arg(long, default_value_t = 0)]
+    startup_delay_secs: u64,
+
+    /// Seconds each interface stays on screen in round-robin mode
+    #[arg(long, default_value_t = 5)]
+    ip_dwell_secs: u64,
+
+    /// Seconds the last known addresses stay on screen while no interface has one
+    #[arg(long, default_value_t = metrics::DEFAULT_IP_GRACE_PERIOD.as_secs())]
+    ip_grace_secs: u64,
+
+    /// Seconds after which the interface addresses are re-read
+    #[arg(long, default_value_t = metrics::DEFAULT_IP_REFRESH_INTERVAL.as_secs())]
+    ip_refresh_secs: u64,
+
+    /// Seconds between network checks, which drop the cached addresses so interfaces that
+    /// came or went are picked up
+    #[arg(long, default_value_t = NETWORK_CHECK_INTERVAL.as_secs())]
+    network_check_secs: u64,
+
+    /// Show one interface at a time, or all of them at once (needs an element with "repeat": "interfaces")
+    #[arg(long, value_enum, default_value = "round-robin")]
+    ip_mode: IpMode,
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum IpMode {
+    /// Cycle through the interfaces, one per IP update
+    RoundRobin,
+    /// Pass every interface to the display on each frame
+    All,
+}
+
+
+// How often the displayed IP is re-read
+const IP_UPDATE_INTERVAL: Duration = Duration::from_millis(2500);
+// Default for how often the IP cache is cleared to pick up network changes
+const NETWORK_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+// How often --startup-delay-secs looks for an interface address
+const STARTUP_POLL_INTERVAL: Duration = Duration::from_secs(1);
+// Consecutive display errors after which the panel is assumed disconnected
+const DISPLAY_ERRORS_BEFORE_RECONNECT: u32 = 3;
+// Reconnect attempts back off from the first to the max delay
+const DISPLAY_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const DISPLAY_RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+// How often the firmware is asked for under-voltage/throttling flags
+const THROTTLE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+// How often the process list is re-read for the proc_cpu/proc_mem bindings
+const PROCESS_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+// Disk usage changes slowly. CPU and RAM usage are refreshed every loop.
+const DISK_UPDATE_INTERVAL: Duration = Duration::from_secs(60);
+// How often the accelerometer is read. A new orientation has to be seen twice in a row.
+#[cfg(feature = "accelerometer")]
+const ORIENTATION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 fn main() -> Result<(), Box<dyn Error>> {
-    let env = Env::default().default_filter_or("info");
-    Builder::from_env(env).init();
+    let args = Args::parse();
+    logging::init(args.log_target, args.quiet)?;
 
     let version = env!("CARGO_PKG_VERSION");
 
@@ -53,11 +294,88 @@ fn main() -> Result<(), Box<dyn Error>> {
     debug!("Target Family:           {}", std::env::consts::FAMILY);
     debug!("Target Architecture:     {}", std::env::consts::ARCH);
 
-    let args = Args::parse();
+    if args.allow_commands {
+        commands::allow();
+    }
+
+    if args.list_bindings {
+        let mut stdout = std::io::stdout().lock();
+        let result = bindings::Binding::ALL.iter()
+            .try_for_each(|binding| writeln!(stdout, "{:<30} {}", binding.name(), binding.description()));
+        ignore_broken_pipe(result)?;
+        return Ok(());
+    }
+
+    if let Some(path) = &args.init_config {
+        default_config::write_default_config(path)?;
+        info!("Default config written to: {}", path);
+        return Ok(());
+    }
+
     debug!("Using config file: {}", args.config);
 
+    if args.print_config {
+        print_config(&args, &mut std::io::stdout().lock())?;
+        return Ok(());
+    }
+
+    if let Some(path) = args.render_to.as_deref().filter(|_| args.demo) {
+        return run_demo(&args, path);
+    }
+
+    if args.check_config {
+        let result = PoeDisplay::load_config_for_panel(&args.config, args.display_size)
+            .and_then(|mut config| Ok(display::render_sample(&mut config, args.temp_unit, args.display_size)?));
+        match result {
+            Ok(frame) if frame.clipped() == 0 => println!("OK: {}", args.config),
+            Ok(frame) => {
+                println!("FAIL: {}: {} pixels drawn outside the panel", args.config, frame.clipped());
+                std::process::exit(1);
+            },
+            Err(e) => {
+                println!("FAIL: {}: {}", args.config, e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(path) = &args.render_to {
+        let mut config = PoeDisplay::load_config(&args.config, args.display_size)?;
+        let frame = display::render_sample(&mut config, args.temp_unit, args.display_size)?;
+        write_frame(&frame, path)?;
+        info!("Rendered sample frame to: {}", path);
+        return Ok(());
+    }
+
+    if args.healthcheck {
+        let cpu_temp = get_cpu_temperature(&thermal_zone_paths(&args.thermal_zone)).map(|temp| temp + args.temp_offset);
+        let fan_running = fan_controller::read_fan_running(args.fan_pin, args.fan_polarity);
+        let problem = match (cpu_temp, &fan_running) {
+            (None, _) => Some("temperature unavailable".to_string()),
+            (_, Err(e)) => Some(format!("fan state unavailable: {}", e)),
+            (Some(temp), _) if temp >= args.temp_critical => Some(format!("temperature at or above {}°C", format_temp(args.temp_critical, TempUnit::Celsius))),
+            (Some(temp), Ok(false)) if temp >= args.temp_on => Some("fan should be running".to_string()),
+            _ => None,
+        };
+        let temp = cpu_temp.map_or("--".to_string(), |temp| format!("{}°C", format_temp(temp, TempUnit::Celsius)));
+        let fan = match fan_running {
+            Ok(true) => "on",
+            Ok(false) => "off",
+            Err(_) => "--",
+        };
+        match problem {
+            None => println!("OK: temp {}, fan {}", temp, fan),
+            Some(problem) => {
+                println!("FAIL: {} (temp {}, fan {})", problem, temp, fan);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
     // Initialize display with potential fallback to default config
-    let mut poe_disp = match PoeDisplay::new(&args.config) {
+    let mut poe_disp = match PoeDisplay::new(&args.config, args.display_size, args.display_address, args.display_init_retries) {
         Ok(disp) => {
             info!("Display initialized with configuration from: {}", args.config);
             disp
@@ -65,340 +383,889 @@ fn main() -> Result<(), Box<dyn Error>> {
         Err(e) => {
             error!("Failed to initialize display: {}", e);
             // Box the error to match the return type
-            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, 
+            return Err(Box::new(std::io::Error::other(
                 format!("Display initialization failed: {}", e))));
         }
     };
 
+    let extra_displays: Vec<ExtraDisplay> = args.extra_display.iter()
+        .map(|(address, path)| ExtraDisplay::new(*address, path, args.display_size))
+        .collect();
+
     // Initialize fan controller with graceful error handling
-    let mut fan_controller = match FanController::new(args.temp_on, args.temp_off) {
+    let load_trigger = args.load_on.map(|load_on| FanTrigger::new(load_on, args.load_off.unwrap_or(load_on * 0.75)));
+    let fan_controller = FanTriggers::new(FanTrigger::new(args.temp_on, args.temp_off), load_trigger)
+        .and_then(|triggers| FanController::new(triggers, args.fan_pin, args.fan_polarity));
+    let mut fan_controller = match fan_controller {
         Ok(fc) => {
-            info!("Fan controller initialized. temp-on: {}, temp-off: {}", 
-                  fc.temp_on, fc.temp_off);
+            info!("Fan controller initialized. temp-on: {}, temp-off: {}, pin: P{}", 
+                  fc.triggers.temp.on, fc.triggers.temp.off, args.fan_pin);
+            if let Some(load) = &fc.triggers.load {
+                info!("Fan also runs on load. load-on: {}, load-off: {}", load.on, load.off);
+            }
             fc
         },
         Err(e) => {
             error!("Failed to initialize fan controller: {}", e);
             // Box the error to match the return type
-            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, 
+            return Err(Box::new(std::io::Error::other(
                 format!("Fan controller initialization failed: {}", e))));
         }
     };
 
-    let mut sys: System = System::new_with_specifics(
-        RefreshKind::new()
-            .with_cpu(CpuRefreshKind::new().with_cpu_usage())
-            .with_memory(MemoryRefreshKind::new().with_ram()),
-    );
-
-    debug!("System initialized. System info:");
+    debug!("System info:");
     debug!("================================");
     debug!("System name:             {}", System::name().unwrap_or_default());
     debug!("System kernel version:   {}", System::kernel_version().unwrap_or_default());
     debug!("System OS version:       {}", System::os_version().unwrap_or_default());
 
-    let mut disk_usage = String::new();
-    let disk_update_interval = Duration::from_secs(60);
-    let mut last_disk_update = Instant::now() - disk_update_interval;
+    #[cfg(feature = "mqtt")]
+    let mqtt_publisher = mqtt::MqttPublisher::new(&args.mqtt);
+
+    #[cfg(feature = "http")]
+    let shared_metrics = args.metrics_port.and_then(|port| {
+        let metrics = http::SharedMetrics::default();
+        match http::start_server(port, metrics.clone()) {
+            Ok(()) => Some(metrics),
+            Err(e) => {
+                error!("Failed to start metrics server on port {}: {}", port, e);
+                None
+            }
+        }
+    });
+
+    #[cfg(feature = "webhook")]
+    let webhook = args.webhook_url.as_deref().map(webhook::Webhook::new);
+
+    let csv_logger = args.log_csv.as_deref().and_then(|path| {
+        match csv_log::CsvLogger::new(path, Duration::from_secs(args.log_csv_interval_secs)) {
+            Ok(logger) => Some(logger),
+            Err(e) => {
+                warn!("Failed to open CSV log {}: {}. CSV logging disabled", path, e);
+                None
+            }
+        }
+    });
+
+    let thermal_zone_paths = thermal_zone_paths(&args.thermal_zone);
+    for (zone, path) in args.thermal_zone.iter().zip(&thermal_zone_paths) {
+        if std::path::Path::new(path).exists() {
+            debug!("Reading CPU temperature from {}", path);
+        } else {
+            warn!("Thermal zone {} not found at {}, skipping it", zone, path);
+        }
+    }
+    if args.temp_offset != 0.0 {
+        info!("Adding {}°C to temperature readings", args.temp_offset);
+    }
+    if let Some(path) = &args.poe_status_path {
+        if !std::path::Path::new(path).exists() {
+            warn!("PoE status file {} not found, the power source will be unavailable", path);
+        }
+    }
+    let throttle_check_interval = THROTTLE_CHECK_INTERVAL.max(Duration::from_millis(args.interval_ms));
+    let disk_update_interval = DISK_UPDATE_INTERVAL.max(Duration::from_millis(args.interval_ms));
+    let process_refresh_interval = PROCESS_REFRESH_INTERVAL.max(Duration::from_millis(args.interval_ms));
     info!("Starting main loop");
     
-    if let Err(e) = fan_controller.fan_off() {
+    // Fill the otherwise blank display during the self-test and first measurements
+    if let Some(state) = args.state_file.as_deref().filter(|_| !args.once).and_then(SavedState::load) {
+        debug!("Showing the readings saved before the restart");
+        if let Err(e) = poe_disp.update_display(&state.display_values(args.temp_unit)) {
+            warn!("Failed to show saved readings: {}", e);
+        }
+    }
+    
+    if args.fan_test_ms > 0 && !args.once {
+        if let Err(e) = fan_controller.self_test(Duration::from_millis(args.fan_test_ms)) {
+            warn!("Fan self-test failed: {}", e);
+        }
+    }
+    if args.once {
+        // Runs from e.g. cron mustn't stop a fan that an earlier run started: carry on from
+        // the state of the pin, so the hysteresis still applies
+        match fan_controller::read_fan_running(args.fan_pin, args.fan_polarity) {
+            Ok(running) => fan_controller.assume_running(running),
+            Err(e) => {
+                warn!("Failed to read the fan state: {}", e);
+                if let Err(e) = fan_controller.fan_off() {
+                    warn!("Failed to turn off fan initially: {}", e);
+                }
+            }
+        }
+    } else if let Err(e) = fan_controller.fan_off() {
         warn!("Failed to turn off fan initially: {}", e);
     }
     
-    let mut iteration_count = 0;
-    let mut ip_info = get_local_ip();
+    if args.once {
+        // CPU usage is measured between two refreshes, so give the first one time to settle
+        thread::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL);
+    } else {
+        match poe_disp.show_splash() {
+            Ok(Some(duration)) => thread::sleep(duration),
+            Ok(None) => {},
+            Err(e) => warn!("Failed to show splash screen: {}", e),
+        }
+    }
     
-    // Add a way to detect network changes
-    let mut previous_ip_info = get_local_ip();
-    let mut network_check_counter = 0;
+    let ip_settings = IpSettings {
+        dwell: Duration::from_secs(args.ip_dwell_secs),
+        grace: Duration::from_secs(args.ip_grace_secs),
+        refresh_interval: Duration::from_secs(args.ip_refresh_secs),
+    };
+    let mut source: Box<dyn MetricsSource> = if args.demo {
+        info!("Demo mode: showing synthetic readings, the fan follows their temperature");
+        Box::new(DemoMetrics::new())
+    } else {
+        Box::new(SystemMetricsSource::new(thermal_zone_paths, ip_settings)
+            .with_temp_offset(args.temp_offset)
+            .with_poe_status_path(args.poe_status_path.clone()))
+    };
+    // The board and HAT don't change while running
+    let model = source.model();
+    if let Some(model) = &model {
+        info!("Running on {}", model);
+    }
+    let hat = source.hat();
+    match (&hat.product, &hat.vendor) {
+        (Some(product), vendor) => info!("HAT: {} by {}", product, vendor.as_deref().unwrap_or("unknown vendor")),
+        (None, _) => debug!("No HAT information in the device tree"),
+    }
+    if args.startup_delay_secs > 0 {
+        wait_for_interface(source.as_mut(), Duration::from_secs(args.startup_delay_secs));
+    }
+    let ip_info = source.local_ip();
 
-    loop {
-        iteration_count += 1;
-        info!("main loop iteration: {}", iteration_count);
-        
-        // Only refresh system info every iteration
-        sys.refresh_cpu_usage();
-        sys.refresh_memory();
-        
-        // Only update IP info every 5 iterations (or similar interval)
-        if iteration_count % 5 == 0 {
-            network_check_counter += 1;
-            
-            // Every 12 network checks (about 1 minute if each loop is 1 second), 
-            // perform a more thorough check to see if the network has changed
-            if network_check_counter >= 15 {
-                network_check_counter = 0;
-                
-                // Force a network refresh by clearing the IP_ADDRESSES cache
-                {
-                    let mut addresses = IP_ADDRESSES.lock().unwrap();
-                    if !addresses.is_empty() {
-                        info!("Periodic network check: Clearing IP cache to force refresh");
-                        addresses.clear();
-                    }
-                }
+    // Derived cadences are wall-clock based so they don't depend on the loop interval,
+    // but never run more often than the loop itself
+    let loop_interval = Duration::from_millis(args.interval_ms);
+    let error_interval = loop_interval / 5;
+    let ip_update_interval = IP_UPDATE_INTERVAL.min(ip_settings.dwell).max(loop_interval);
+    let network_check_interval = Duration::from_secs(args.network_check_secs).max(loop_interval);
+    let render_interval = args.render_interval_ms.map_or(loop_interval, Duration::from_millis).max(loop_interval);
+    let idle_render_interval = args.idle_render_interval_ms.map_or(render_interval, Duration::from_millis).max(render_interval);
+    debug!("Loop interval: {:?}, render interval: {:?} (idle: {:?}), IP update interval: {:?}, network check interval: {:?}",
+           loop_interval, render_interval, idle_render_interval, ip_update_interval, network_check_interval);
+
+    // SIGUSR1 turns the picture upside down, for panels mounted the other way round
+    let flip_requested = Arc::new(AtomicBool::new(false));
+    if let Err(e) = signal_hook::flag::register(signal_hook::consts::SIGUSR1, Arc::clone(&flip_requested)) {
+        warn!("Failed to register SIGUSR1 handler, orientation can't be flipped at runtime: {}", e);
+    }
+    
+    // SIGUSR2 shows or hides diagnostics (loop count, IP cache and timers) over the layout
+    let overlay_toggled = Arc::new(AtomicBool::new(false));
+    if let Err(e) = signal_hook::flag::register(signal_hook::consts::SIGUSR2, Arc::clone(&overlay_toggled)) {
+        warn!("Failed to register SIGUSR2 handler, the debug overlay can't be shown: {}", e);
+    }
+    
+    #[cfg(feature = "accelerometer")]
+    let accelerometer = match args.accelerometer.then(accelerometer::Accelerometer::new) {
+        Some(Ok(accelerometer)) => Some(accelerometer),
+        Some(Err(e)) => {
+            warn!("Accelerometer unavailable, the orientation stays fixed: {}", e);
+            None
+        },
+        None => None,
+    };
+    
+    // SIGTERM (e.g. systemctl stop) and SIGINT end the loop so the state can be saved
+    let terminate = Arc::new(AtomicBool::new(false));
+    for signal in [signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+        if let Err(e) = signal_hook::flag::register(signal, Arc::clone(&terminate)) {
+            warn!("Failed to register handler for signal {}: {}", signal, e);
+        }
+    }
+
+    let last_carrier = source.carrier();
+    #[cfg(feature = "accelerometer")]
+    let config_portrait = poe_disp.orientation().is_portrait();
+    let mut monitor = Monitor {
+        args: &args,
+        poe_disp,
+        extra_displays,
+        fan_controller,
+        #[cfg(feature = "mqtt")]
+        mqtt_publisher,
+        #[cfg(feature = "http")]
+        shared_metrics,
+        #[cfg(feature = "webhook")]
+        webhook,
+        csv_logger,
+        model,
+        hat,
+        source,
+        temp_average: TemperatureAverage::new(args.temp_samples as usize),
+        throttle_status: ThrottleStatus::Ok,
+        throttle_check_interval,
+        last_throttle_check: Instant::now() - throttle_check_interval,
+        disk_usage_value: None,
+        disk_bytes: None,
+        disk_update_interval,
+        last_disk_update: Instant::now() - disk_update_interval,
+        disk_warning: false,
+        processes: Vec::new(),
+        process_refresh_interval,
+        last_process_refresh: Instant::now() - process_refresh_interval,
+        iteration_count: 0,
+        previous_ip_info: ip_info.clone(),
+        ip_info,
+        loop_interval,
+        error_interval,
+        ip_update_interval,
+        network_check_interval,
+        render_interval,
+        idle_render_interval,
+        current_render_interval: render_interval,
+        last_render: None,
+        last_ip_update: Instant::now(),
+        last_network_check: Instant::now(),
+        last_carrier,
+        display_errors: 0,
+        next_reconnect: None,
+        reconnect_delay: DISPLAY_RECONNECT_DELAY,
+        flip_requested,
+        overlay_toggled,
+        show_overlay: false,
+        #[cfg(feature = "accelerometer")]
+        accelerometer,
+        #[cfg(feature = "accelerometer")]
+        config_portrait,
+        #[cfg(feature = "accelerometer")]
+        pending_orientation: None,
+        #[cfg(feature = "accelerometer")]
+        last_orientation_check: Instant::now() - ORIENTATION_CHECK_INTERVAL,
+        last_state: None,
+    };
+
+    if args.once {
+        return match monitor.step() {
+            Some(result) => result.map(|_| ()).map_err(|e| format!("Failed to update display: {}", e).into()),
+            None => Err("The display was not updated".into()),
+        };
+    }
+
+    while !terminate.load(Ordering::Relaxed) {
+        let pause = match monitor.step() {
+            Some(result) => monitor.after_update(result),
+            None => monitor.loop_interval,
+        };
+        thread::sleep(pause);
+    }
+
+    info!("Shutting down");
+    if let (Some(path), Some(state)) = (&args.state_file, &monitor.last_state) {
+        match state.save(path) {
+            Ok(()) => debug!("Saved the last readings to {}", path),
+            Err(e) => warn!("Failed to save state to {}: {}", path, e),
+        }
+    }
+    Ok(())
+}
+
+// State the main loop keeps between passes
+struct Monitor<'a> {
+    args: &'a Args,
+    poe_disp: PoeDisplay,
+    extra_displays: Vec<ExtraDisplay>,
+    fan_controller: FanController,
+    #[cfg(feature = "mqtt")]
+    mqtt_publisher: Option<mqtt::MqttPublisher>,
+    #[cfg(feature = "http")]
+    shared_metrics: Option<http::SharedMetrics>,
+    #[cfg(feature = "webhook")]
+    webhook: Option<webhook::Webhook>,
+    csv_logger: Option<csv_log::CsvLogger>,
+    // The board and HAT don't change while running
+    model: Option<String>,
+    hat: HatInfo,
+    // Every reading comes from here
+    source: Box<dyn MetricsSource>,
+    temp_average: TemperatureAverage,
+    throttle_status: ThrottleStatus,
+    throttle_check_interval: Duration,
+    last_throttle_check: Instant,
+    disk_usage_value: Option<f64>,
+    disk_bytes: Option<ByteUsage>,
+    disk_update_interval: Duration,
+    last_disk_update: Instant,
+    // Whether the disk usage is at or above --disk-warn-percent
+    disk_warning: bool,
+    processes: Vec<ProcessUsage>,
+    process_refresh_interval: Duration,
+    last_process_refresh: Instant,
+    iteration_count: u64,
+    ip_info: Option<InterfaceIp>,
+    previous_ip_info: Option<InterfaceIp>,
+    loop_interval: Duration,
+    error_interval: Duration,
+    ip_update_interval: Duration,
+    network_check_interval: Duration,
+    render_interval: Duration,
+    idle_render_interval: Duration,
+    // Doubled after each render that left the display unchanged, up to the idle interval
+    current_render_interval: Duration,
+    last_render: Option<Instant>,
+    last_ip_update: Instant,
+    last_network_check: Instant,
+    last_carrier: Option<bool>,
+    display_errors: u32,
+    // Set while the display is considered disconnected: when to try reconnecting next
+    next_reconnect: Option<Instant>,
+    reconnect_delay: Duration,
+    flip_requested: Arc<AtomicBool>,
+    overlay_toggled: Arc<AtomicBool>,
+    show_overlay: bool,
+    #[cfg(feature = "accelerometer")]
+    accelerometer: Option<accelerometer::Accelerometer>,
+    // Landscape or portrait of the --config layout, to tell which layout an orientation needs
+    #[cfg(feature = "accelerometer")]
+    config_portrait: bool,
+    #[cfg(feature = "accelerometer")]
+    pending_orientation: Option<display_types::Orientation>,
+    #[cfg(feature = "accelerometer")]
+    last_orientation_check: Instant,
+    last_state: Option<SavedState>,
+}
+impl Monitor<'_> {
+    // One pass of the main loop: sample the metrics, control the fan and, when it's due,
+    // update the displays. The result of updating the main display; None if it wasn't
+    // updated this time.
+    fn step(&mut self) -> Option<Result<bool, display::DisplayError>> {
+        self.iteration_count += 1;
+        debug!(iteration = self.iteration_count; "main loop iteration: {}", self.iteration_count);
+
+        // A cable being plugged in or pulled is picked up right away instead of at the next
+        // network check. Without carrier info only the timers apply.
+        let carrier = self.source.carrier();
+        let link_changed = carrier != self.last_carrier;
+        if link_changed {
+            info!("Link state changed: {:?} -> {:?}", self.last_carrier, carrier);
+            self.last_carrier = carrier;
+        }
+
+        // Only update IP info every few seconds
+        if link_changed || self.last_ip_update.elapsed() >= self.ip_update_interval {
+            self.last_ip_update = Instant::now();
+
+            // About once a minute, perform a more thorough check to see if the network has changed
+            // A link that just came up may take a few seconds to get an address, so look
+            // again on every IP update until it has one
+            let awaiting_address = carrier == Some(true) && self.ip_info.is_none();
+            if link_changed || awaiting_address || self.last_network_check.elapsed() >= self.network_check_interval {
+                self.last_network_check = Instant::now();
+
+                // Force a network refresh by clearing the IP cache
+                debug!("Network check: Clearing IP cache to force refresh");
+                self.source.refresh_network();
             }
-            
-            ip_info = get_local_ip();
-            
+
+            self.ip_info = self.source.local_ip();
+
             // Check if IP info has changed, and log it clearly if it has
-            if ip_info.1 != previous_ip_info.1 || ip_info.0 != previous_ip_info.0 {
-                info!("IP information changed. Old: {:?}, New: {:?}", previous_ip_info, ip_info);
-                previous_ip_info = ip_info.clone();
+            if self.ip_info != self.previous_ip_info {
+                let (interface, ip) = interface_and_ip(&self.ip_info);
+                info!(interface = interface, ip = ip;
+                      "IP information changed. Old: {:?}, New: {:?}", self.previous_ip_info, self.ip_info);
+                self.previous_ip_info = self.ip_info.clone();
             }
         }
+        // Empty when no interface has an address
+        let (interface, ip) = interface_and_ip(&self.ip_info);
+
+        // CPU and RAM are cheap to read and refreshed every iteration; disks, processes and
+        // the throttling flags have their own, slower intervals below. The raw temperature
+        // stays available; everything downstream uses the smoothed value.
+        // Readings that fail are None and shown as a placeholder.
+        let cpu_temp_raw = self.source.cpu_temperature();
+        let cpu_temp = cpu_temp_raw.map(|temp| self.temp_average.push(temp));
+        let cpu_temp_str = cpu_temp.map(|temp| format_temp(temp, TempUnit::Celsius));
+        let cpu_usage_value = self.source.cpu_usage();
+        let cpu_usage = format_percent(cpu_usage_value as f64);
+        let ram_bytes = self.source.ram_bytes();
+        let ram_usage_value = ram_bytes.map(|ram| ram.percent());
+        let ram_usage = ram_usage_value.map(format_percent);
+        let poe = self.source.poe_status().map(power_source_label);
 
-        let cpu_temp = get_cpu_temperature();
-        let cpu_temp_str = format!("{:.1}", cpu_temp);
-        let cpu_usage = format!("{:.1}", sys.global_cpu_info().cpu_usage());
-        let ram_usage = format!("{:.1}", get_ram_usage(&sys));
-        
         // Fan control logic
-        trace!("Checking fan controller. Fan running: {}", fan_controller.is_running);
-        trace!("CPU Temp: {}", cpu_temp);
-        
-        if fan_controller.is_running {
-            if cpu_temp <= fan_controller.temp_off {
-                if let Err(e) = fan_controller.fan_off() {
-                    warn!("Failed to turn off fan: {}", e);
-                }
-            }
-        } else if cpu_temp >= fan_controller.temp_on {
-            if let Err(e) = fan_controller.fan_on() {
+        trace!("Checking fan controller. Fan running: {}", self.fan_controller.is_running);
+        trace!("CPU Temp: {:?} (raw: {:?})", cpu_temp, cpu_temp_raw);
+
+        // Without a temperature reading the temperature trigger keeps its current state
+        let fan_was_running = self.fan_controller.is_running;
+        let load_average = self.fan_controller.triggers.load.is_some().then(|| self.source.load_average());
+        let fan_action = self.fan_controller.evaluate(cpu_temp, load_average);
+        trace!("Load average: {:?}, fan action: {:?}", load_average, fan_action);
+        match fan_action {
+            FanAction::TurnOn => if let Err(e) = self.fan_controller.fan_on() {
                 warn!("Failed to turn on fan: {}", e);
+            },
+            FanAction::TurnOff => if let Err(e) = self.fan_controller.fan_off() {
+                warn!("Failed to turn off fan: {}", e);
+            },
+            FanAction::NoChange => {},
+        }
+
+        if self.fan_controller.is_running != fan_was_running {
+            let event = if self.fan_controller.is_running { "fan_on" } else { "fan_off" };
+            let temp = cpu_temp.unwrap_or_default();
+            info!(event = event, temp = temp; "Fan state changed: {} at {:.1}°C", event, temp);
+
+            #[cfg(feature = "webhook")]
+            if let Some(webhook) = &self.webhook {
+                webhook.send_event(event, serde_json::json!({ "temp": temp }));
             }
+
+            // Show the new fan state right away instead of at the next render interval
+            self.last_render = None;
         }
-        
+
         // Update disk usage less frequently
-        if last_disk_update.elapsed() >= disk_update_interval {
-            last_disk_update = Instant::now();
-            disk_usage = format!("{:.1}", get_disk_usage());
-            info!("Updated disk usage: {}", disk_usage);
-        }
-        
-        let (interface_phys, interface_numvlan) = split_interface(&ip_info.0);
-        
+        if self.last_disk_update.elapsed() >= self.disk_update_interval {
+            self.last_disk_update = Instant::now();
+            self.disk_bytes = self.source.disk_bytes();
+            self.disk_usage_value = self.disk_bytes.map(|disk| disk.percent());
+            #[cfg(feature = "http")]
+            if let Some(shared_metrics) = &self.shared_metrics {
+                shared_metrics.lock().unwrap().disk_usage = self.disk_usage_value;
+            }
+            debug!("Updated disk usage: {:?}", self.disk_usage_value);
+
+            // Only crossings are reported, not every update while the disk stays full
+            if let (Some(threshold), Some(usage)) = (self.args.disk_warn_percent, self.disk_usage_value) {
+                if (usage >= threshold) != self.disk_warning {
+                    self.disk_warning = usage >= threshold;
+                    let event = if self.disk_warning { "disk_full" } else { "disk_ok" };
+                    if self.disk_warning {
+                        warn!(event = event, usage = usage; "Disk usage at {:.1}%, at or above {}%", usage, threshold);
+                    } else {
+                        info!(event = event, usage = usage; "Disk usage back down to {:.1}%", usage);
+                    }
+
+                    #[cfg(feature = "webhook")]
+                    if let Some(webhook) = &self.webhook {
+                        webhook.send_event(event, serde_json::json!({ "disk_usage": usage }));
+                    }
+                }
+            }
+        }
+        let disk_usage = self.disk_usage_value.map(format_percent);
+
+        // Walking /proc is comparatively expensive, so processes are refreshed less often, and
+        // only for layouts that show them
+        if self.poe_disp.shows_processes() && self.last_process_refresh.elapsed() >= self.process_refresh_interval {
+            self.last_process_refresh = Instant::now();
+            self.processes = self.source.processes();
+            trace!("Updated {} processes", self.processes.len());
+        }
+
+        if self.last_throttle_check.elapsed() >= self.throttle_check_interval {
+            self.last_throttle_check = Instant::now();
+            let status = self.source.throttle_status();
+            if status != self.throttle_status {
+                match status {
+                    ThrottleStatus::UnderVoltage | ThrottleStatus::Throttled => warn!("Throttle status changed: {}", status.as_str()),
+                    _ => info!("Throttle status changed: {}", status.as_str()),
+                }
+                self.throttle_status = status;
+            }
+        }
+
+        if let Some(logger) = &mut self.csv_logger {
+            let fan_state = if self.fan_controller.is_running { "ON" } else { "OFF" };
+            // Unavailable readings are left empty
+            let row = [
+                cpu_temp_str.as_deref().unwrap_or(""), &cpu_usage, ram_usage.as_deref().unwrap_or(""),
+                disk_usage.as_deref().unwrap_or(""), fan_state, interface, ip,
+            ];
+            if let Err(e) = logger.log(&row) {
+                // Most likely the disk is full; don't keep trying every loop
+                warn!("Failed to write CSV log: {}. CSV logging disabled", e);
+                self.csv_logger = None;
+            }
+        }
+
+        #[cfg(feature = "http")]
+        if let Some(shared_metrics) = &self.shared_metrics {
+            let mut metrics = shared_metrics.lock().unwrap();
+            metrics.cpu_temperature = cpu_temp;
+            metrics.cpu_usage = cpu_usage_value;
+            metrics.ram_usage = ram_usage_value;
+            metrics.fan_running = self.fan_controller.is_running;
+            // The fan is only switched on or off, so it runs at either 0 or full speed
+            metrics.fan_duty_percent = if self.fan_controller.is_running { 100.0 } else { 0.0 };
+            metrics.interface = interface.to_string();
+            metrics.ip_address = ip.to_string();
+        }
+
+        #[cfg(feature = "mqtt")]
+        if let Some(publisher) = &self.mqtt_publisher {
+            let fan_state = if self.fan_controller.is_running { "ON" } else { "OFF" };
+            // Unavailable readings are not published
+            let metrics = [
+                ("cpu_temp", cpu_temp_str.as_deref()),
+                ("cpu_usage", Some(cpu_usage.as_str())),
+                ("ram_usage", ram_usage.as_deref()),
+                ("disk_usage", disk_usage.as_deref()),
+                ("fan", Some(fan_state)),
+                ("interface", self.ip_info.as_ref().map(|info| info.0.as_str())),
+                ("ip", self.ip_info.as_ref().map(|info| info.1.as_str())),
+            ];
+            let available: Vec<(&str, &str)> = metrics.iter()
+                .filter_map(|(name, value)| value.map(|value| (*name, value)))
+                .collect();
+            publisher.publish_metrics(&available);
+        }
+
+        #[cfg(feature = "accelerometer")]
+        if self.last_orientation_check.elapsed() >= ORIENTATION_CHECK_INTERVAL {
+            self.last_orientation_check = Instant::now();
+            if let Some(accelerometer) = &mut self.accelerometer {
+                match accelerometer.orientation() {
+                    // Switch once the new orientation is confirmed by the next reading
+                    Ok(Some(orientation)) if orientation != self.poe_disp.orientation() && self.pending_orientation != Some(orientation) => {
+                        self.pending_orientation = Some(orientation);
+                    },
+                    Ok(Some(orientation)) if orientation != self.poe_disp.orientation() => {
+                        self.pending_orientation = None;
+                        let layout = if orientation.is_portrait() == self.config_portrait {
+                            Some(self.args.config.as_str())
+                        } else {
+                            self.args.rotated_config.as_deref()
+                        };
+                        match layout {
+                            Some(layout) => {
+                                if let Err(e) = self.poe_disp.set_orientation(orientation, layout) {
+                                    warn!("Failed to switch the display to {:?}: {}", orientation, e);
+                                    self.next_reconnect = Some(Instant::now());
+                                }
+                                self.last_render = None;
+                            },
+                            None => debug!("Device turned to {:?}, but there is no --rotated-config", orientation),
+                        }
+                    },
+                    Ok(_) => self.pending_orientation = None,
+                    Err(e) => debug!("Failed to read the accelerometer: {}", e),
+                }
+            }
+        }
+
+        if self.flip_requested.swap(false, Ordering::Relaxed) {
+            if let Err(e) = self.poe_disp.flip_orientation() {
+                warn!("Failed to re-initialize display after flipping orientation: {}", e);
+                self.next_reconnect = Some(Instant::now());
+            }
+            self.last_render = None;
+        }
+
+        if self.overlay_toggled.swap(false, Ordering::Relaxed) {
+            self.show_overlay = !self.show_overlay;
+            info!("Debug overlay {}", if self.show_overlay { "shown" } else { "hidden" });
+            if !self.show_overlay {
+                self.poe_disp.set_overlay(Vec::new());
+            }
+            self.last_render = None;
+        }
+
+        // Sampling and fan control run every loop; the display only when it's due
+        if self.last_render.is_some_and(|t| t.elapsed() < self.current_render_interval) {
+            return None;
+        }
+
+        // In "all" mode the display gets the whole list; the round-robin entry above still
+        // drives logging and the other outputs
+        let interfaces = match self.args.ip_mode {
+            IpMode::RoundRobin => self.ip_info.iter().cloned().collect(),
+            IpMode::All => self.source.local_ips(),
+        };
+
+        let display_values = DisplayValues {
+            interfaces: &interfaces,
+            cpu_usage: cpu_usage_value,
+            cpu_temp: cpu_temp.map(|temp| convert_temp(temp, self.args.temp_unit)),
+            ram_usage: ram_usage_value,
+            disk_usage: self.disk_usage_value,
+            ram_bytes,
+            disk_bytes: self.disk_bytes,
+            processes: &self.processes,
+            throttled: (self.throttle_status != ThrottleStatus::Unknown).then(|| self.throttle_status.as_str()),
+            poe,
+            fan_running: self.fan_controller.is_running,
+            model: self.model.as_deref(),
+            hat_product: self.hat.product.as_deref(),
+            hat_vendor: self.hat.vendor.as_deref(),
+            // Of the interface that elements which aren't repeated show; eth0 without one
+            net_errors: self.source.net_errors(interfaces.first().map(|ip_info| ip_info.0.as_str())),
+        };
+
         // Log values we're about to display for debugging
         debug!(
-            "Display values: ip:{}, interface:{}, phys:{}, vlan:{}, octets:{:?}, cpu:{}, temp:{}, ram:{}, disk:{}",
-            ip_info.1, ip_info.0, interface_phys, interface_numvlan, ip_info.2, 
-            cpu_usage, cpu_temp_str, ram_usage, disk_usage
+            "Display values: interfaces:{:?}, cpu:{}, temp:{:?}, ram:{:?}, disk:{:?}, throttled:{}",
+            interfaces, cpu_usage, display_values.cpu_temp, ram_usage, disk_usage, self.throttle_status.as_str()
         );
-        
+
+        // The other displays go first, so they keep updating while the main one is gone
+        for extra in &mut self.extra_displays {
+            extra.update(&display_values);
+        }
+
+        // While the display is disconnected, keep monitoring and controlling the fan but
+        // only try to bring the panel back, with increasing delays between attempts
+        if let Some(at) = self.next_reconnect {
+            if Instant::now() >= at {
+                match self.poe_disp.reconnect() {
+                    Ok(()) => {
+                        info!("Display reconnected");
+                        self.next_reconnect = None;
+                        self.display_errors = 0;
+                        self.reconnect_delay = DISPLAY_RECONNECT_DELAY;
+                    },
+                    Err(e) => {
+                        warn!("Failed to reconnect display: {}. Retrying in {:?}", e, self.reconnect_delay);
+                        self.next_reconnect = Some(Instant::now() + self.reconnect_delay);
+                        self.reconnect_delay = (self.reconnect_delay * 2).min(DISPLAY_RECONNECT_MAX_DELAY);
+                    }
+                }
+            }
+            if self.next_reconnect.is_some() {
+                return None;
+            }
+        }
+
+        if self.show_overlay {
+            let ip_cache = self.source.ip_cache_status();
+            self.poe_disp.set_overlay(vec![
+                format!("#{}", self.iteration_count),
+                format!("if {}/{}", ip_cache.index + 1, ip_cache.count),
+                format!("ip {}s", ip_cache.age.map_or(0, |age| age.as_secs())),
+                format!("net {}s", self.last_network_check.elapsed().as_secs()),
+                format!("err {}", self.display_errors),
+            ]);
+        }
+
         // Update the display with consistent error handling
-        match poe_disp.update_display(
-            &ip_info,
-            &ip_info.1,      // IP Address e.g., 192.168.0.1
-            &ip_info.0,      // Interface e.g., eth0.99
-            &interface_phys,  // Physical interface e.g., eth0
-            &interface_numvlan, // VLAN interface e.g., 99
-            &ip_info.2,      // IP Octets e.g., [192, 168, 0, 1]
-            &cpu_usage,
-            &cpu_temp_str,       // CPU temperature
-            &ram_usage,
-            &disk_usage,
-        ) {
-            Ok(_) => {
+        let result = self.poe_disp.update_display(&display_values);
+        if result.is_ok() && self.args.state_file.is_some() {
+            self.last_state = Some(SavedState::new(&display_values, cpu_temp));
+        }
+        Some(result)
+    }
+
+    // Count display errors and adapt the render interval after an update. How long to wait
+    // before the next pass.
+    fn after_update(&mut self, result: Result<bool, display::DisplayError>) -> Duration {
+        match result {
+            Ok(changed) => {
                 trace!("Display updated successfully");
+                self.display_errors = 0;
+                self.last_render = Some(Instant::now());
+                self.current_render_interval = if changed {
+                    self.render_interval
+                } else {
+                    (self.current_render_interval * 2).min(self.idle_render_interval)
+                };
                 // Slow down the update rate to reduce flickering
-                thread::sleep(Duration::from_millis(500));
+                self.loop_interval
+            },
+            Err(e) if e.is_bus_error() => {
+                // Could be a one-off glitch; only assume the display is gone if it keeps failing
+                self.display_errors += 1;
+                warn!("Failed to update display ({}/{}): {}", self.display_errors, DISPLAY_ERRORS_BEFORE_RECONNECT, e);
+                if self.display_errors >= DISPLAY_ERRORS_BEFORE_RECONNECT {
+                    warn!("Display not responding after {} attempts, reconnecting", self.display_errors);
+                    self.next_reconnect = Some(Instant::now());
+                }
+                // Sleep even on error to prevent rapid retries
+                self.error_interval
             },
             Err(e) => {
-                error!("Failed to update display: {:?}", e);
+                error!("Failed to update display: {}", e);
                 // Sleep even on error to prevent rapid retries
-                thread::sleep(Duration::from_millis(100));
+                self.error_interval
             }
         }
     }
 }
 
-fn get_cpu_temperature() -> f32 {
-    match fs::read_to_string("/sys/class/thermal/thermal_zone0/temp") {
-        Ok(temp_contents) => {
-            temp_contents.trim().parse::<f32>().unwrap_or(0.0) / 1000.0
-        },
-        Err(_) => {
-            warn!("Failed to read CPU temperature, returning 0.0");
-            0.0
+// Render synthetic readings to `path` every loop, without a Pi, display or fan. The fan
+// triggers are evaluated on the readings, so fan transitions show up in the frame and the log.
+fn run_demo(args: &Args, path: &str) -> Result<(), Box<dyn Error>> {
+    let mut config = PoeDisplay::load_config(&args.config, args.display_size)?;
+    let load_trigger = args.load_on.map(|load_on| FanTrigger::new(load_on, args.load_off.unwrap_or(load_on * 0.75)));
+    let mut triggers = FanTriggers::new(FanTrigger::new(args.temp_on, args.temp_off), load_trigger)?;
+    let mut source = DemoMetrics::new();
+    let mut fan_running = false;
+    info!("Demo mode: rendering synthetic readings to {} every {}ms", path, args.interval_ms);
+
+    loop {
+        let cpu_temp = source.cpu_temperature();
+        if triggers.update(cpu_temp, Some(source.load_average())) != fan_running {
+            fan_running = !fan_running;
+            info!("Fan turned {} at {:.1}°C", if fan_running { "on" } else { "off" }, cpu_temp.unwrap_or_default());
         }
+
+        let interfaces = source.local_ips();
+        let ram_bytes = source.ram_bytes();
+        let disk_bytes = source.disk_bytes();
+        let model = source.model();
+        let hat = source.hat();
+        let values = DisplayValues {
+            interfaces: &interfaces,
+            cpu_usage: source.cpu_usage(),
+            cpu_temp: cpu_temp.map(|temp| convert_temp(temp, args.temp_unit)),
+            ram_usage: ram_bytes.map(|ram| ram.percent()),
+            disk_usage: disk_bytes.map(|disk| disk.percent()),
+            ram_bytes,
+            disk_bytes,
+            processes: &[],
+            throttled: Some(source.throttle_status().as_str()),
+            poe: source.poe_status().map(power_source_label),
+            fan_running,
+            model: model.as_deref(),
+            hat_product: hat.product.as_deref(),
+            hat_vendor: hat.vendor.as_deref(),
+            net_errors: source.net_errors(interfaces.first().map(|ip_info| ip_info.0.as_str())),
+        };
+        let frame = display::render_to_framebuffer(&mut config, &values, args.display_size)?;
+        write_frame(&frame, path)?;
+        trace!("Rendered demo frame at {:?}°C", cpu_temp);
+        thread::sleep(Duration::from_millis(args.interval_ms));
     }
 }
 
-fn get_ram_usage(sys: &System) -> f64 {
-    let total_memory = sys.total_memory();
-    if total_memory == 0 {
-        return 0.0;
+// The layout as the monitor uses it on the configured panel, as JSON
+fn print_config(args: &Args, out: &mut impl Write) -> Result<(), Box<dyn Error>> {
+    let config = PoeDisplay::load_config(&args.config, args.display_size)?;
+    let json = serde_json::to_string_pretty(&config)?;
+    ignore_broken_pipe(writeln!(out, "{}", json))?;
+    Ok(())
+}
+
+// Output piped into e.g. `head` may be cut short, which isn't an error
+fn ignore_broken_pipe(result: std::io::Result<()>) -> std::io::Result<()> {
+    match result {
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
     }
-    let used_memory = sys.used_memory();
-    (used_memory as f64 / total_memory as f64) * 100.0
 }
 
-fn get_disk_usage() -> f64 {
-    let mut disks = Disks::new_with_refreshed_list();
-    if let Some(disk) = disks.first_mut() {
-        disk.refresh();
-        let total_space = disk.total_space();
-        let available_space = disk.available_space();
-        if total_space > 0 {
-            (1.0 - (available_space as f64 / total_space as f64)) * 100.0
-        } else {
-            0.0
-        }
+// PNG, or text for a .txt path
+fn write_frame(frame: &FrameBuffer, path: &str) -> std::io::Result<()> {
+    if path.ends_with(".txt") {
+        frame.write_text(path)
     } else {
-        0.0
+        frame.write_png(path)
     }
 }
 
-fn collect_interface_ips() -> Vec<(String, String, [u8; 4])> {
-    info!("Starting to collect interface IPs...");
-    
-    let output = match Command::new("ip").args(&["addr"]).output() {
-        Ok(output) => output,
-        Err(e) => {
-            error!("Failed to execute ip command: {}", e);
-            return vec![("NoInterface".to_string(), "0.0.0.0".to_string(), [0, 0, 0, 0])];
+// Poll until an interface has an address, e.g. while DHCP is still running after boot.
+// Gives up after `timeout`, after which the display starts without one.
+fn wait_for_interface(source: &mut dyn MetricsSource, timeout: Duration) {
+    let started = Instant::now();
+    loop {
+        if let Some((interface, ip, _)) = source.local_ip() {
+            info!("{} has address {} after {:?}", interface, ip, started.elapsed());
+            return;
         }
-    };
-
-    let output_str = String::from_utf8_lossy(&output.stdout);
-    debug!("Raw 'ip addr' output: \n{}", output_str);
-    
-    let mut ips = Vec::new();
-    let mut current_interface = String::new();
-
-    info!("Parsing interfaces from ip command output...");
-    
-    for line in output_str.lines() {
-        debug!("Processing line: {}", line);
-        
-        if line.starts_with(char::is_numeric) {
-            if let Some(interface) = line.split(": ").nth(1)
-                .map(|s| s.split(' ').next().unwrap()
-                .trim_end_matches(':')
-                .split('@').next().unwrap()) {
-                current_interface = interface.to_string();
-                debug!("Found interface: {}", current_interface);
-            }
-        } else if line.contains("inet ") && current_interface.starts_with("eth0") {
-            debug!("Found inet line for {}: {}", current_interface, line);
-            
-            if let Some(ip) = line
-                .split_whitespace()
-                .find(|s| s.contains("/"))
-                .map(|s| s.split('/').next().unwrap().to_string())
-            {
-                debug!("Extracted IP: {}", ip);
-                
-                // Parse IP into [u8;4] octets
-                let octs: Vec<u8> = ip
-                    .split('.')
-                    .map(|num| num.parse().unwrap_or(0))
-                    .collect();
-                if octs.len() == 4 {
-                    info!("Adding interface: {}, IP: {}, octets: {:?}", 
-                          current_interface, ip, [octs[0], octs[1], octs[2], octs[3]]);
-                    ips.push((current_interface.clone(), ip, [octs[0], octs[1], octs[2], octs[3]]));
-                } else {
-                    warn!("Invalid IP format for {}: {}", current_interface, ip);
-                }
-            }
+        if started.elapsed() >= timeout {
+            warn!("No interface got an address within {:?}, starting without one", timeout);
+            return;
         }
+        debug!("Waiting for an interface to get an address");
+        thread::sleep(STARTUP_POLL_INTERVAL);
+        // An empty lookup is cached like any other, so look again
+        source.refresh_network();
     }
-    
-    if ips.is_empty() {
-        warn!("No interfaces and IPs were found matching criteria");
-        // Return a dummy entry so we have something to display
-        ips.push(("NoInterface".to_string(), "0.0.0.0".to_string(), [0, 0, 0, 0]));
-    } else {
-        info!("Successfully collected {} interface IPs: {:?}", ips.len(), ips);
+}
+
+// Interface name and address for logs and exports, empty when there is none
+fn interface_and_ip(ip_info: &Option<(String, String, [u8; 4])>) -> (&str, &str) {
+    ip_info.as_ref().map_or(("", ""), |(interface, ip, _)| (interface.as_str(), ip.as_str()))
+}
+
+fn thermal_zone_paths(zones: &[u32]) -> Vec<String> {
+    zones.iter().map(|zone| format!("/sys/class/thermal/thermal_zone{}/temp", zone)).collect()
+}
+
+// Hexadecimal with a 0x prefix, or decimal
+fn parse_i2c_address(value: &str) -> Result<u8, String> {
+    let parsed = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    match parsed {
+        Ok(address) if address <= 0x7f => Ok(address),
+        _ => Err(format!("{:?} is not a 7-bit I2C address", value)),
     }
-    
-    ips
 }
 
-fn get_local_ip() -> (String, String, [u8; 4]) {
-    // Use a result pattern to handle potential errors while obtaining locks
-    let result = (|| -> Result<(String, String, [u8; 4]), Box<dyn std::error::Error>> {
-        let mut addresses = IP_ADDRESSES.lock().unwrap();
-        let mut index = CURRENT_INDEX.lock().unwrap();
-        let mut last_refresh = LAST_IP_REFRESH.lock().unwrap();
-        
-        // Force a refresh of IP addresses every 5 minutes (300 seconds)
-        let refresh_interval = Duration::from_secs(300);
-        let should_refresh = addresses.is_empty() || last_refresh.elapsed() >= refresh_interval;
-
-        // Log current state
-        info!(
-            "get_local_ip called. Current addresses: {:?}, index: {}, time since last refresh: {:?}, should refresh: {}",
-            addresses, *index, last_refresh.elapsed(), should_refresh
-        );
-        
-        // Refresh if needed
-        if should_refresh {
-            info!("Refreshing IP addresses...");
-            *addresses = collect_interface_ips();
-            *last_refresh = Instant::now();
-            
-            // Reset index when we refresh
-            *index = 0;
-        }
-
-        // Safely get an address or return a default
-        if addresses.is_empty() {
-            warn!("No IP addresses found, returning dummy record");
-            return Ok(("NoInterface".to_string(), "0.0.0.0".to_string(), [0, 0, 0, 0]));
-        }
-        
-        if *index >= addresses.len() {
-            info!("Index {} is out of bounds, resetting to 0", *index);
-            *index = 0; // Reset if out of bounds
-        }
-        
-        let (iface, ip, ip_octets) = addresses[*index].clone();
-        *index = (*index + 1) % addresses.len();
-        
-        info!("Returning IP info: interface={}, ip={}, octets={:?}, next index will be {}", 
-              iface, ip, ip_octets, *index);
-        Ok((iface, ip, ip_octets))
-    })();
-    
-    // Handle any potential errors with mutex locks
-    match result {
-        Ok(info) => info,
-        Err(e) => {
-            error!("Error in get_local_ip: {}. Returning default values.", e);
-            ("NoInterface".to_string(), "0.0.0.0".to_string(), [0, 0, 0, 0])
-        }
+fn parse_extra_display(value: &str) -> Result<(u8, String), String> {
+    let (address, path) = value.split_once('=').ok_or("expected ADDRESS=PATH")?;
+    if path.is_empty() {
+        return Err("the layout path is missing".to_string());
     }
+    Ok((parse_i2c_address(address)?, path.to_string()))
 }
 
-fn split_interface(interface: &str) -> (String, String) {
-    let parts: Vec<&str> = interface.split('.').collect();
-    if parts.len() == 2 {
-        // Handle potential edge case where interface name is too short
-        if parts[0].is_empty() {
-            return (interface.to_string(), String::new());
-        }
-        
-        // Safely extract the last character and handle potential string slicing issues
-        let phys = parts[0].to_string();
-        if phys.is_empty() {
-            return (interface.to_string(), String::new());
-        }
-        
-        // Safely get the last character
-        if let Some(last_char) = phys.chars().last() {
-            let numvlan = format!("{}.{}", last_char, parts[1]);
-            // Safely create the physical interface name without the last character
-            if phys.len() > 1 {
-                return (phys[..phys.len() - 1].to_string(), numvlan);
-            } else {
-                return (phys, numvlan);
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // Args are read from the environment too, so no test parses them while another one
+    // changes it
+    static ENV: Mutex<()> = Mutex::new(());
+
+    fn parse_args(args: &[&str]) -> Args {
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        Args::try_parse_from(args).unwrap()
+    }
+
+    #[test]
+    fn arguments_override_the_environment_which_overrides_defaults() {
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        std::env::remove_var("TEMP_ON");
+        std::env::remove_var("RUSTBERRY_CONFIG");
+        let args = Args::try_parse_from(["rustberry-poe-monitor"]).unwrap();
+        assert_eq!(args.temp_on, 60.0);
+        assert_eq!(args.config, "/etc/rustberry-poe-monitor/portrait.json");
+
+        std::env::set_var("TEMP_ON", "55");
+        std::env::set_var("RUSTBERRY_CONFIG", "/tmp/layout.json");
+        let args = Args::try_parse_from(["rustberry-poe-monitor"]).unwrap();
+        assert_eq!(args.temp_on, 55.0);
+        assert_eq!(args.config, "/tmp/layout.json");
+
+        let args = Args::try_parse_from(["rustberry-poe-monitor", "--temp-on", "65", "--config", "landscape.json"]).unwrap();
+        assert_eq!(args.temp_on, 65.0);
+        assert_eq!(args.config, "landscape.json");
+
+        std::env::remove_var("TEMP_ON");
+        std::env::remove_var("RUSTBERRY_CONFIG");
+    }
+
+    #[test]
+    fn temp_offset_may_be_negative() {
+        let args = parse_args(&["rustberry-poe-monitor", "--temp-offset", "-2.5"]);
+        assert_eq!(args.temp_offset, -2.5);
+        let args = parse_args(&["rustberry-poe-monitor"]);
+        assert_eq!(args.temp_offset, 0.0);
+    }
+
+    #[test]
+    fn printed_config_has_the_panel_size_filled_in() {
+        let path = std::env::temp_dir().join(format!("rustberry-{}-portrait.json", std::process::id()));
+        std::fs::write(&path, r#"{ "orientation": "portrait", "elements": [] }"#).unwrap();
+
+        for (size, width, height) in [("128x32", 32, 128), ("128x64", 64, 128)] {
+            let args = parse_args(&["rustberry-poe-monitor", "--print-config", "--display-size", size,
+                                    "--config", path.to_str().unwrap()]);
+            let mut out = Vec::new();
+            print_config(&args, &mut out).unwrap();
+            let config: serde_json::Value = serde_json::from_slice(&out).unwrap();
+            assert_eq!((config["width"].as_i64(), config["height"].as_i64()), (Some(width), Some(height)), "{}", size);
         }
+
+        std::fs::remove_file(path).unwrap();
     }
-    
-    // Default fallback
-    (interface.to_string(), String::new())
-}
\ No newline at end of file
+}
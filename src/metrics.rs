@@ -0,0 +1,985 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+use log::{debug, error, trace, warn};
+use serde::{Deserialize, Serialize};
+use sysinfo::{CpuRefreshKind, Disks, MemoryRefreshKind, ProcessRefreshKind, RefreshKind, System};
+
+use crate::throttle::{self, ThrottleStatus};
+
+// Interfaces whose names start with this are shown, including their VLANs
+const INTERFACE_PREFIX: &str = "eth0";
+
+// (interface, ip, octets), e.g. ("eth0.100", "192.168.1.2", [192, 168, 1, 2])
+pub type InterfaceIp = (String, String, [u8; 4]);
+
+pub const DEFAULT_THERMAL_ZONE_PATH: &str = "/sys/class/thermal/thermal_zone0/temp";
+// Device tree files naming the board, tried in order
+const MODEL_PATHS: [&str; 2] = ["/proc/device-tree/model", "/sys/firmware/devicetree/base/model"];
+// Filled in by the firmware from the EEPROM of an attached HAT
+const HAT_PATH: &str = "/proc/device-tree/hat";
+
+// How long the last known addresses are kept while no interface has one, e.g. during a
+// DHCP renewal
+pub const DEFAULT_IP_GRACE_PERIOD: Duration = Duration::from_secs(30);
+// Maximum age of the cached addresses before they are re-read
+pub const DEFAULT_IP_REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+// How often the addresses are re-read while the last known ones are kept
+const IP_RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+// How interface addresses are looked up and cycled through
+#[derive(Clone, Copy, Debug)]
+pub struct IpSettings {
+    // How long each interface is shown in round-robin order
+    pub dwell: Duration,
+    pub grace: Duration,
+    pub refresh_interval: Duration,
+}
+
+impl Default for IpSettings {
+    fn default() -> Self {
+        IpSettings {
+            dwell: Duration::from_secs(5),
+            grace: DEFAULT_IP_GRACE_PERIOD,
+            refresh_interval: DEFAULT_IP_REFRESH_INTERVAL,
+        }
+    }
+}
+
+lazy_static! {
+    static ref IP_CACHE: Mutex<IpCache> = Mutex::new(IpCache::new(collect_interface_ips));
+}
+
+// One reading of everything the monitor shows. Readings that could not be taken are None.
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    // Degrees Celsius
+    pub cpu_temperature: Option<f32>,
+    // Percentages
+    pub cpu_usage: f32,
+    pub ram_usage: Option<f64>,
+    pub disk_usage: Option<f64>,
+    pub throttle: ThrottleStatus,
+    // (interface, ip, octets) of every matching interface
+    pub interfaces: Vec<InterfaceIp>,
+}
+
+// Collect all metrics at once. `sys` and `disks` should be kept between calls: CPU usage is
+// measured since its previous refresh, so the first reading is not meaningful.
+pub fn collect_metrics(sys: &mut System, disks: &mut Disks) -> Metrics {
+    sys.refresh_cpu_usage();
+    sys.refresh_memory();
+    Metrics {
+        cpu_temperature: get_cpu_temperature(&[DEFAULT_THERMAL_ZONE_PATH]),
+        cpu_usage: sys.global_cpu_info().cpu_usage(),
+        ram_usage: get_ram_usage(sys),
+        disk_usage: get_disk_usage(disks),
+        throttle: throttle::get_throttle_status(),
+        interfaces: get_all_local_ips(&IpSettings::default()),
+    }
+}
+
+// Where the monitor's readings come from. The monitor only reads through this trait, so the
+// real collectors (`SystemMetricsSource`) can be swapped for synthetic ones, e.g.
+// `demo::MockMetricsSource` with fixed values or `demo::DemoMetrics` for --demo.
+pub trait MetricsSource {
+    // Degrees Celsius
+    fn cpu_temperature(&mut self) -> Option<f32>;
+    // Percent, measured since the previous call
+    fn cpu_usage(&mut self) -> f32;
+    fn ram_bytes(&mut self) -> Option<ByteUsage>;
+    fn disk_bytes(&mut self) -> Option<ByteUsage>;
+    // Average number of runnable processes over the last minute
+    fn load_average(&mut self) -> f32;
+    fn throttle_status(&mut self) -> ThrottleStatus;
+    fn processes(&mut self) -> Vec<ProcessUsage>;
+    // The interface to show now, cycling through them in round-robin order
+    fn local_ip(&mut self) -> Option<InterfaceIp>;
+    // Every interface with an address
+    fn local_ips(&mut self) -> Vec<InterfaceIp>;
+
+    // Forget cached addresses so network changes are picked up
+    fn refresh_network(&mut self) {}
+
+    // Whether the network cable is plugged in; None if that isn't known
+    fn carrier(&mut self) -> Option<bool> {
+        None
+    }
+
+    // Error and drop counters of the given interface, or of eth0 without one; None if they
+    // aren't known
+    fn net_errors(&mut self, _interface: Option<&str>) -> Option<NetErrors> {
+        None
+    }
+
+    // Whether the board is powered over PoE; None if that isn't known
+    fn poe_status(&mut self) -> Option<bool>;
+    // Board model, e.g. "Raspberry Pi 4 Model B Rev 1.4"
+    fn model(&mut self) -> Option<String>;
+    fn hat(&mut self) -> HatInfo;
+    // Which of the interfaces local_ip() returns now, for diagnostics
+    fn ip_cache_status(&mut self) -> IpCacheStatus;
+
+    fn ram_usage(&mut self) -> Option<f64> {
+        self.ram_bytes().map(|ram| ram.percent())
+    }
+
+    fn disk_usage(&mut self) -> Option<f64> {
+        self.disk_bytes().map(|disk| disk.percent())
+    }
+
+    // Everything at once
+    fn collect(&mut self) -> Metrics {
+        Metrics {
+            cpu_temperature: self.cpu_temperature(),
+            cpu_usage: self.cpu_usage(),
+            ram_usage: self.ram_usage(),
+            disk_usage: self.disk_usage(),
+            throttle: self.throttle_status(),
+            interfaces: self.local_ips(),
+        }
+    }
+}
+
+// Readings of the running system. `System` and `Disks` are kept between calls, so CPU usage
+// can be measured and the disks aren't listed again every time.
+pub struct SystemMetricsSource {
+    sys: System,
+    disks: Disks,
+    thermal_zone_paths: Vec<String>,
+    // Degrees added to every temperature reading
+    temp_offset: f32,
+    // Value file telling whether the board is powered over PoE, see get_poe_status
+    poe_status_path: Option<String>,
+    ip_settings: IpSettings,
+}
+
+impl SystemMetricsSource {
+    // The temperature is the highest of the given thermal zone files
+    pub fn new(thermal_zone_paths: Vec<String>, ip_settings: IpSettings) -> Self {
+        let sys = System::new_with_specifics(
+            RefreshKind::new()
+                .with_cpu(CpuRefreshKind::new().with_cpu_usage())
+                .with_memory(MemoryRefreshKind::new().with_ram())
+                .with_processes(process_refresh_kind()),
+        );
+        SystemMetricsSource { sys, disks: Disks::new_with_refreshed_list(), thermal_zone_paths, temp_offset: 0.0, poe_status_path: None, ip_settings }
+    }
+
+    // Calibrate the temperature against a reference thermometer, e.g. -2.5 if the sensor
+    // reads 2.5°C too high
+    pub fn with_temp_offset(mut self, offset: f32) -> Self {
+        self.temp_offset = offset;
+        self
+    }
+
+    pub fn with_poe_status_path(mut self, path: Option<String>) -> Self {
+        self.poe_status_path = path;
+        self
+    }
+}
+
+impl Default for SystemMetricsSource {
+    fn default() -> Self {
+        Self::new(vec![DEFAULT_THERMAL_ZONE_PATH.to_string()], IpSettings::default())
+    }
+}
+
+impl MetricsSource for SystemMetricsSource {
+    fn cpu_temperature(&mut self) -> Option<f32> {
+        get_cpu_temperature(&self.thermal_zone_paths).map(|temp| temp + self.temp_offset)
+    }
+
+    fn cpu_usage(&mut self) -> f32 {
+        self.sys.refresh_cpu_usage();
+        self.sys.global_cpu_info().cpu_usage()
+    }
+
+    fn ram_bytes(&mut self) -> Option<ByteUsage> {
+        self.sys.refresh_memory();
+        get_ram_bytes(&self.sys)
+    }
+
+    fn disk_bytes(&mut self) -> Option<ByteUsage> {
+        get_disk_bytes(&mut self.disks)
+    }
+
+    fn load_average(&mut self) -> f32 {
+        get_load_average()
+    }
+
+    fn throttle_status(&mut self) -> ThrottleStatus {
+        throttle::get_throttle_status()
+    }
+
+    fn processes(&mut self) -> Vec<ProcessUsage> {
+        self.sys.refresh_processes_specifics(process_refresh_kind());
+        get_processes(&self.sys)
+    }
+
+    fn local_ip(&mut self) -> Option<InterfaceIp> {
+        get_local_ip(&self.ip_settings)
+    }
+
+    fn local_ips(&mut self) -> Vec<InterfaceIp> {
+        get_all_local_ips(&self.ip_settings)
+    }
+
+    fn refresh_network(&mut self) {
+        clear_ip_cache();
+    }
+
+    fn carrier(&mut self) -> Option<bool> {
+        get_carrier()
+    }
+
+    fn net_errors(&mut self, interface: Option<&str>) -> Option<NetErrors> {
+        get_net_errors(interface.unwrap_or(INTERFACE_PREFIX))
+    }
+
+    fn poe_status(&mut self) -> Option<bool> {
+        get_poe_status(self.poe_status_path.as_deref())
+    }
+
+    fn model(&mut self) -> Option<String> {
+        get_pi_model()
+    }
+
+    fn hat(&mut self) -> HatInfo {
+        get_hat_info()
+    }
+
+    fn ip_cache_status(&mut self) -> IpCacheStatus {
+        ip_cache_status()
+    }
+}
+
+// Process details needed for the proc_cpu/proc_mem bindings
+fn process_refresh_kind() -> ProcessRefreshKind {
+    ProcessRefreshKind::new().with_cpu().with_memory()
+}
+
+// Drop the cached interface IPs so the next lookup re-reads them
+pub fn clear_ip_cache() {
+    IP_CACHE.lock().unwrap().force_refresh();
+}
+
+// Sensor files hold a few bytes; anything longer is cut off rather than read into memory
+const MAX_VALUE_FILE_SIZE: u64 = 4096;
+
+// Trimmed contents of a small text file such as a sysfs attribute. None if it can't be read
+// or is empty.
+pub fn read_value_file(path: &str) -> Option<String> {
+    let mut contents = String::new();
+    match File::open(path).and_then(|file| file.take(MAX_VALUE_FILE_SIZE).read_to_string(&mut contents)) {
+        Ok(_) => Some(contents.trim().to_string()).filter(|contents| !contents.is_empty()),
+        Err(e) => {
+            trace!("Failed to read {}: {}", path, e);
+            None
+        }
+    }
+}
+
+// Board model from the device tree, e.g. "Raspberry Pi 4 Model B Rev 1.4". None on
+// machines without a device tree.
+pub fn get_pi_model() -> Option<String> {
+    MODEL_PATHS.iter().find_map(|path| read_device_tree_string(path))
+}
+
+// Product and vendor of the attached HAT, e.g. "PoE HAT (B)" by "Waveshare", as read from its
+// EEPROM at boot. None for HATs without an EEPROM or when no HAT is attached.
+#[derive(Clone, Debug, Default)]
+pub struct HatInfo {
+    pub product: Option<String>,
+    pub vendor: Option<String>,
+}
+
+pub fn get_hat_info() -> HatInfo {
+    HatInfo {
+        product: read_device_tree_string(&format!("{}/product", HAT_PATH)),
+        vendor: read_device_tree_string(&format!("{}/vendor", HAT_PATH)),
+    }
+}
+
+fn read_device_tree_string(path: &str) -> Option<String> {
+    match fs::read(path) {
+        Ok(contents) => parse_device_tree_string(&contents),
+        Err(e) => {
+            debug!("Failed to read {}: {}", path, e);
+            None
+        }
+    }
+}
+
+// Device tree strings end with a NUL
+fn parse_device_tree_string(contents: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(contents);
+    Some(text.trim_end_matches('\0').trim().to_string()).filter(|text| !text.is_empty())
+}
+
+// Highest temperature of the given thermal zone files. Zones that can't be read are
+// skipped; None if none of them could.
+pub fn get_cpu_temperature<P: AsRef<str>>(paths: &[P]) -> Option<f32> {
+    let max = paths.iter()
+        .filter_map(|path| read_zone_temperature(path.as_ref()))
+        .reduce(f32::max);
+    if max.is_none() {
+        warn!("Failed to read the CPU temperature from any thermal zone");
+    }
+    max
+}
+
+fn read_zone_temperature(path: &str) -> Option<f32> {
+    match fs::read_to_string(path) {
+        Ok(contents) => {
+            let temp = parse_millidegrees(&contents);
+            if temp.is_none() {
+                debug!("Failed to parse temperature {:?} from {}", contents.trim(), path);
+            }
+            temp
+        },
+        Err(e) => {
+            debug!("Failed to read temperature from {}: {}", path, e);
+            None
+        }
+    }
+}
+
+// Thermal zones report millidegrees Celsius, e.g. "48312\n"
+pub fn parse_millidegrees(contents: &str) -> Option<f32> {
+    contents.trim().parse::<f32>().ok().map(|millidegrees| millidegrees / 1000.0)
+}
+
+// Whether the monitored interface has a link, from its sysfs carrier file. None if the
+// interface doesn't exist or is administratively down, where the file can't be read.
+pub fn get_carrier() -> Option<bool> {
+    let path = format!("/sys/class/net/{}/carrier", INTERFACE_PREFIX);
+    match fs::read_to_string(&path) {
+        Ok(contents) => match contents.trim() {
+            "1" => Some(true),
+            "0" => Some(false),
+            _ => None,
+        },
+        Err(e) => {
+            trace!("Failed to read carrier from {}: {}", path, e);
+            None
+        }
+    }
+}
+
+// Receive/transmit errors and dropped packets of an interface, counted since it came up
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NetErrors {
+    pub rx_errors: u64,
+    pub tx_errors: u64,
+    // Dropped while receiving or sending
+    pub drops: u64,
+}
+
+impl NetErrors {
+    // Errors since an earlier reading. A counter that went backwards (the interface was
+    // reset) counts from zero.
+    pub fn since(&self, earlier: &NetErrors) -> NetErrors {
+        let delta = |now: u64, before: u64| if now >= before { now - before } else { now };
+        NetErrors {
+            rx_errors: delta(self.rx_errors, earlier.rx_errors),
+            tx_errors: delta(self.tx_errors, earlier.tx_errors),
+            drops: delta(self.drops, earlier.drops),
+        }
+    }
+}
+
+// Error counters of an interface, e.g. "eth0" or the VLAN "eth0.100", from its sysfs
+// statistics. Rising counts on a PoE link usually mean a bad cable or a duplex mismatch. None
+// if the interface doesn't exist.
+pub fn get_net_errors(interface: &str) -> Option<NetErrors> {
+    let counter = |name: &str| -> Option<u64> {
+        let path = format!("/sys/class/net/{}/statistics/{}", interface, name);
+        match fs::read_to_string(&path) {
+            Ok(contents) => contents.trim().parse().ok(),
+            Err(e) => {
+                trace!("Failed to read {}: {}", path, e);
+                None
+            }
+        }
+    };
+    Some(NetErrors {
+        rx_errors: counter("rx_errors")?,
+        tx_errors: counter("tx_errors")?,
+        drops: counter("rx_dropped")? + counter("tx_dropped")?,
+    })
+}
+
+// How the power source is shown by the poe binding
+pub fn power_source_label(poe: bool) -> &'static str {
+    if poe { "PoE" } else { "USB" }
+}
+
+// Whether the board is powered over PoE, read from a sysfs/GPIO value file where "1" means
+// PoE and "0" means another supply. None if no such file is configured or it can't be read,
+// as most HATs (including the Waveshare PoE HAT (B)) don't report their power source.
+pub fn get_poe_status(path: Option<&str>) -> Option<bool> {
+    let path = path?;
+    match fs::read_to_string(path) {
+        Ok(contents) => match contents.trim() {
+            "1" => Some(true),
+            "0" => Some(false),
+            other => {
+                debug!("Unexpected PoE status {:?} in {}", other, path);
+                None
+            }
+        },
+        Err(e) => {
+            debug!("Failed to read PoE status from {}: {}", path, e);
+            None
+        }
+    }
+}
+
+// Used and total size of the RAM or the disk, in bytes
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ByteUsage {
+    pub used: u64,
+    pub total: u64,
+}
+
+impl ByteUsage {
+    pub fn percent(&self) -> f64 {
+        (self.used as f64 / self.total as f64) * 100.0
+    }
+}
+
+// Average number of runnable processes over the last minute
+pub fn get_load_average() -> f32 {
+    System::load_average().one as f32
+}
+
+pub fn get_ram_bytes(sys: &System) -> Option<ByteUsage> {
+    let total = sys.total_memory();
+    if total == 0 {
+        return None;
+    }
+    Some(ByteUsage { used: sys.used_memory(), total })
+}
+
+pub fn get_ram_usage(sys: &System) -> Option<f64> {
+    get_ram_bytes(sys).map(|ram| ram.percent())
+}
+
+// Size of the disk mounted at "/", or of the first disk if there is none. `disks` should be
+// kept between calls so the mounts aren't listed again for every reading; the list is only
+// re-read while it is empty. Space that is not available to unprivileged users (including
+// the reserved blocks) counts as used.
+pub fn get_disk_bytes(disks: &mut Disks) -> Option<ByteUsage> {
+    if disks.list().is_empty() {
+        disks.refresh_list();
+    }
+    let root = root_disk_index(disks.iter().map(|disk| disk.mount_point()));
+    let disk = disks.list_mut().get_mut(root)?;
+    disk.refresh();
+    disk_space_usage(disk.total_space(), disk.available_space())
+}
+
+// Position of the disk mounted at "/" in a list of mount points, or 0 if there is none
+fn root_disk_index<'a>(mount_points: impl IntoIterator<Item = &'a Path>) -> usize {
+    mount_points.into_iter().position(|mount_point| mount_point == Path::new("/")).unwrap_or(0)
+}
+
+// None for a disk without a size, e.g. a pseudo filesystem
+fn disk_space_usage(total: u64, available: u64) -> Option<ByteUsage> {
+    (total > 0).then(|| ByteUsage { used: total.saturating_sub(available), total })
+}
+
+pub fn get_disk_usage(disks: &mut Disks) -> Option<f64> {
+    get_disk_bytes(disks).map(|disk| disk.percent())
+}
+
+// Resource usage of one running process
+#[derive(Clone, Debug)]
+pub struct ProcessUsage {
+    pub pid: u32,
+    pub name: String,
+    // Percent of one core, so busy multi-threaded processes can exceed 100
+    pub cpu_usage: f32,
+    // Resident memory in bytes
+    pub memory: u64,
+}
+
+impl ProcessUsage {
+    // Whether `query` is this process' name or PID
+    pub fn matches(&self, query: &str) -> bool {
+        self.name == query || query.parse() == Ok(self.pid)
+    }
+}
+
+// Every process known to `sys`, which must have refreshed processes with CPU and memory
+// info. Threads are left out, they share their process' memory.
+pub fn get_processes(sys: &System) -> Vec<ProcessUsage> {
+    sys.processes().iter()
+        .filter(|(_, process)| process.thread_kind().is_none())
+        .map(|(pid, process)| ProcessUsage {
+            pid: pid.as_u32(),
+            name: process.name().to_string(),
+            cpu_usage: process.cpu_usage(),
+            memory: process.memory(),
+        })
+        .collect()
+}
+
+// Extract (interface, ip, octets) for every IPv4 address in `ip addr` output that belongs
+// to an interface starting with one of the given names (so "eth0" also matches "eth0.100")
+pub fn parse_ip_addr_output(output: &str, interfaces: &[String]) -> Vec<InterfaceIp> {
+    let mut ips = Vec::new();
+    let mut current_interface = String::new();
+
+    debug!("Parsing interfaces from ip command output...");
+    
+    for line in output.lines() {
+        trace!("Processing line: {}", line);
+        
+        if line.starts_with(char::is_numeric) {
+            if let Some(interface) = line.split(": ").nth(1)
+                .map(|s| s.split(' ').next().unwrap()
+                .trim_end_matches(':')
+                .split('@').next().unwrap()) {
+                current_interface = interface.to_string();
+                debug!("Found interface: {}", current_interface);
+            }
+        } else if line.contains("inet ") && interfaces.iter().any(|prefix| current_interface.starts_with(prefix.as_str())) {
+            debug!("Found inet line for {}: {}", current_interface, line);
+            
+            if let Some(ip) = line
+                .split_whitespace()
+                .find(|s| s.contains("/"))
+                .map(|s| s.split('/').next().unwrap().to_string())
+            {
+                debug!("Extracted IP: {}", ip);
+                
+                // Parse IP into [u8;4] octets
+                let octs: Vec<u8> = ip
+                    .split('.')
+                    .map(|num| num.parse().unwrap_or(0))
+                    .collect();
+                if octs.len() == 4 {
+                    debug!("Adding interface: {}, IP: {}, octets: {:?}", 
+                          current_interface, ip, [octs[0], octs[1], octs[2], octs[3]]);
+                    ips.push((current_interface.clone(), ip, [octs[0], octs[1], octs[2], octs[3]]));
+                } else {
+                    warn!("Invalid IP format for {}: {}", current_interface, ip);
+                }
+            }
+        }
+    }
+    
+    ips
+}
+
+fn collect_interface_ips() -> Vec<InterfaceIp> {
+    debug!("Starting to collect interface IPs...");
+    
+    let output = match Command::new("ip").args(["addr"]).output() {
+        Ok(output) => output,
+        Err(e) => {
+            error!("Failed to execute ip command: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let output_str = String::from_utf8_lossy(&output.stdout);
+    trace!("Raw 'ip addr' output: \n{}", output_str);
+    
+    let interfaces = [INTERFACE_PREFIX.to_string()];
+    let ips = parse_ip_addr_output(&output_str, &interfaces);
+    
+    if ips.is_empty() {
+        warn!("No interfaces and IPs were found matching criteria");
+    } else {
+        debug!("Successfully collected {} interface IPs: {:?}", ips.len(), ips);
+    }
+    
+    ips
+}
+
+// Interface addresses with the round-robin position. `collect` reads the current addresses,
+// normally from `ip addr`.
+struct IpCache {
+    collect: fn() -> Vec<InterfaceIp>,
+    addresses: Vec<InterfaceIp>,
+    index: usize,
+    // When the round-robin last moved to the current interface
+    last_switch: Option<Instant>,
+    // None until the first lookup, and after a forced refresh
+    last_refresh: Option<Instant>,
+    // When the addresses were last read, even if none were found
+    last_attempt: Option<Instant>,
+    // Last non-empty set of addresses and when it was collected; survives forced refreshes
+    last_good: Option<(Vec<InterfaceIp>, Instant)>,
+}
+
+impl IpCache {
+    fn new(collect: fn() -> Vec<InterfaceIp>) -> Self {
+        IpCache { collect, addresses: Vec::new(), index: 0, last_switch: None, last_refresh: None, last_attempt: None, last_good: None }
+    }
+
+    // Make the next lookup re-collect the addresses
+    fn force_refresh(&mut self) {
+        self.addresses.clear();
+        self.last_refresh = None;
+        self.last_attempt = None;
+    }
+
+    // Re-collect the interface IPs if the cache is empty or older than the refresh interval,
+    // restarting the round-robin. Returns true if it was refreshed. If no interface has an
+    // address, the last known addresses are kept for up to the grace period, and the lookup
+    // is retried every IP_RETRY_INTERVAL until then.
+    fn refresh_if_stale(&mut self, settings: &IpSettings) -> bool {
+        let retried_recently = self.last_attempt.is_some_and(|t| t.elapsed() < IP_RETRY_INTERVAL);
+        let should_refresh = !retried_recently && self.last_refresh.is_none_or(|t| t.elapsed() >= settings.refresh_interval);
+        trace!("Last IP refresh: {:?}, should refresh: {}", self.last_refresh, should_refresh);
+        if !should_refresh {
+            return false;
+        }
+
+        debug!("Refreshing IP addresses...");
+        let collected = (self.collect)();
+        self.last_attempt = Some(Instant::now());
+        match &self.last_good {
+            _ if !collected.is_empty() => self.last_good = Some((collected.clone(), Instant::now())),
+            Some((known, seen)) if seen.elapsed() < settings.grace => {
+                debug!("No addresses found, keeping the last known ones from {:?} ago", seen.elapsed());
+                self.addresses = known.clone();
+                return false;
+            },
+            _ => {},
+        }
+        self.addresses = collected;
+        self.last_refresh = Some(Instant::now());
+        self.index = 0;
+        self.last_switch = None;
+        true
+    }
+
+    // The interface to show, moving on to the next one once the current one has been
+    // returned for the dwell time
+    fn current(&mut self, settings: &IpSettings) -> Option<InterfaceIp> {
+        self.refresh_if_stale(settings);
+        trace!("Current addresses: {:?}, index: {}", self.addresses, self.index);
+
+        if self.addresses.is_empty() {
+            debug!("No IP addresses found");
+            return None;
+        }
+        if self.index >= self.addresses.len() {
+            debug!("Index {} is out of bounds, resetting to 0", self.index);
+            self.index = 0;
+        }
+
+        match self.last_switch {
+            Some(t) if t.elapsed() >= settings.dwell => {
+                self.index = (self.index + 1) % self.addresses.len();
+                self.last_switch = Some(Instant::now());
+            },
+            Some(_) => {},
+            None => self.last_switch = Some(Instant::now()),
+        }
+
+        let ip_info = self.addresses[self.index].clone();
+        trace!("Returning IP info: {:?}, index {}", ip_info, self.index);
+        Some(ip_info)
+    }
+}
+
+// Round-robin position and age of the cached interface IPs, for diagnostics
+#[derive(Clone, Copy, Debug)]
+pub struct IpCacheStatus {
+    pub index: usize,
+    pub count: usize,
+    // None until the first lookup, and after a forced refresh
+    pub age: Option<Duration>,
+}
+
+pub fn ip_cache_status() -> IpCacheStatus {
+    let cache = IP_CACHE.lock().unwrap();
+    IpCacheStatus { index: cache.index, count: cache.addresses.len(), age: cache.last_refresh.map(|t| t.elapsed()) }
+}
+
+// All collected interface IPs, for showing them at once instead of in turn.
+// Empty if no matching interface has had an address for the grace period.
+pub fn get_all_local_ips(settings: &IpSettings) -> Vec<InterfaceIp> {
+    let mut cache = IP_CACHE.lock().unwrap();
+    cache.refresh_if_stale(settings);
+    cache.addresses.clone()
+}
+
+// The interface IP to show in round-robin order, moving on to the next interface once the
+// current one has been returned for the dwell time. None if no matching interface has had
+// an address for the grace period.
+pub fn get_local_ip(settings: &IpSettings) -> Option<InterfaceIp> {
+    IP_CACHE.lock().unwrap().current(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `ip addr` on a Pi with a VLAN, a secondary address, an IPv6-only VLAN and two
+    // interfaces that are down
+    const IP_ADDR_OUTPUT: &str = "\
+1: lo: <LOOPBACK,UP,LOWER_UP> mtu 65536 qdisc noqueue state UNKNOWN group default qlen 1000
+    link/loopback 00:00:00:00:00:00 brd 00:00:00:00:00:00
+    inet 127.0.0.1/8 scope host lo
+       valid_lft forever preferred_lft forever
+    inet6 ::1/128 scope host noprefixroute
+       valid_lft forever preferred_lft forever
+2: eth0: <BROADCAST,MULTICAST,UP,LOWER_UP> mtu 1500 qdisc mq state UP group default qlen 1000
+    link/ether dc:a6:32:01:02:03 brd ff:ff:ff:ff:ff:ff
+    inet 192.168.1.42/24 brd 192.168.1.255 scope global dynamic noprefixroute eth0
+       valid_lft 85813sec preferred_lft 75013sec
+    inet 192.168.1.43/24 brd 192.168.1.255 scope global secondary eth0
+       valid_lft forever preferred_lft forever
+    inet6 fe80::dea6:32ff:fe01:203/64 scope link
+       valid_lft forever preferred_lft forever
+3: wlan0: <NO-CARRIER,BROADCAST,MULTICAST,UP> mtu 1500 qdisc pfifo_fast state DOWN group default qlen 1000
+    link/ether dc:a6:32:01:02:04 brd ff:ff:ff:ff:ff:ff
+4: eth0.100@eth0: <BROADCAST,MULTICAST,UP,LOWER_UP> mtu 1500 qdisc noqueue state UP group default qlen 1000
+    link/ether dc:a6:32:01:02:03 brd ff:ff:ff:ff:ff:ff
+    inet 10.0.100.42/24 brd 10.0.100.255 scope global eth0.100
+       valid_lft forever preferred_lft forever
+5: eth0.200@eth0: <BROADCAST,MULTICAST,UP,LOWER_UP> mtu 1500 qdisc noqueue state UP group default qlen 1000
+    link/ether dc:a6:32:01:02:03 brd ff:ff:ff:ff:ff:ff
+    inet6 2001:db8::42/64 scope global dynamic mngtmpaddr
+       valid_lft 86391sec preferred_lft 14391sec
+6: eth0.300@eth0: <BROADCAST,MULTICAST> mtu 1500 qdisc noop state DOWN group default qlen 1000
+    link/ether dc:a6:32:01:02:03 brd ff:ff:ff:ff:ff:ff
+";
+
+    fn parse(interfaces: &[&str]) -> Vec<InterfaceIp> {
+        let interfaces: Vec<String> = interfaces.iter().map(|name| name.to_string()).collect();
+        parse_ip_addr_output(IP_ADDR_OUTPUT, &interfaces)
+    }
+
+    #[test]
+    fn parses_every_ipv4_address_of_matching_interfaces() {
+        assert_eq!(parse(&["eth0"]), vec![
+            ("eth0".to_string(), "192.168.1.42".to_string(), [192, 168, 1, 42]),
+            ("eth0".to_string(), "192.168.1.43".to_string(), [192, 168, 1, 43]),
+            // The VLAN's name is cut at the "@"
+            ("eth0.100".to_string(), "10.0.100.42".to_string(), [10, 0, 100, 42]),
+        ]);
+    }
+
+    #[test]
+    fn skips_ipv6_only_and_down_interfaces() {
+        assert!(parse(&["eth0.200"]).is_empty());
+        assert!(parse(&["eth0.300"]).is_empty());
+        assert!(parse(&["wlan0"]).is_empty());
+    }
+
+    #[test]
+    fn only_returns_the_requested_interfaces() {
+        assert_eq!(parse(&["lo"]), vec![("lo".to_string(), "127.0.0.1".to_string(), [127, 0, 0, 1])]);
+        assert!(parse(&["eth1"]).is_empty());
+        assert!(parse_ip_addr_output("", &["eth0".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn net_errors_are_read_for_the_given_interface() {
+        // The loopback interface exists on every Linux machine
+        assert!(get_net_errors("lo").is_some());
+        assert_eq!(get_net_errors("nonexistent0"), None);
+    }
+
+    #[test]
+    fn device_tree_strings_lose_their_nul() {
+        assert_eq!(parse_device_tree_string(b"Raspberry Pi 4 Model B Rev 1.4\0").as_deref(), Some("Raspberry Pi 4 Model B Rev 1.4"));
+        assert_eq!(parse_device_tree_string(b"PoE HAT (B)\n\0").as_deref(), Some("PoE HAT (B)"));
+        assert_eq!(parse_device_tree_string(b"Waveshare").as_deref(), Some("Waveshare"));
+        assert_eq!(parse_device_tree_string(b"\0"), None);
+        assert_eq!(parse_device_tree_string(b""), None);
+    }
+
+    #[test]
+    fn parses_millidegrees() {
+        assert_eq!(parse_millidegrees("48312\n"), Some(48.312));
+        assert_eq!(parse_millidegrees("  51000 "), Some(51.0));
+        assert_eq!(parse_millidegrees("-5250\n"), Some(-5.25));
+        assert_eq!(parse_millidegrees("0"), Some(0.0));
+        assert_eq!(parse_millidegrees(""), None);
+        assert_eq!(parse_millidegrees("\n"), None);
+        assert_eq!(parse_millidegrees("48.3C"), None);
+        assert_eq!(parse_millidegrees("not a number"), None);
+    }
+
+    // A thermal zone file with `contents` in the temp directory, named after the test
+    fn zone_file(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("rustberry-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn cpu_temperature_is_the_hottest_readable_zone() {
+        let cool = zone_file("zone-cool", "41000\n");
+        let hot = zone_file("zone-hot", "52500\n");
+        let garbage = zone_file("zone-garbage", "ERROR\n");
+        let missing = "/nonexistent/thermal_zone9/temp".to_string();
+
+        assert_eq!(get_cpu_temperature(&[cool.clone(), hot.clone()]), Some(52.5));
+        // Zones that are missing or hold garbage are skipped
+        assert_eq!(get_cpu_temperature(&[missing.clone(), garbage.clone(), cool.clone()]), Some(41.0));
+        assert_eq!(get_cpu_temperature(&[missing, garbage.clone()]), None);
+        assert_eq!(get_cpu_temperature::<&str>(&[]), None);
+
+        for path in [cool, hot, garbage] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn temp_offset_applies_to_available_readings() {
+        let zone = zone_file("zone-offset", "52500\n");
+        let mut source = SystemMetricsSource::new(vec![zone.clone()], IpSettings::default()).with_temp_offset(-2.5);
+        assert_eq!(source.cpu_temperature(), Some(50.0));
+
+        let mut source = SystemMetricsSource::new(vec!["/nonexistent/thermal_zone9/temp".to_string()], IpSettings::default())
+            .with_temp_offset(-2.5);
+        assert_eq!(source.cpu_temperature(), None);
+
+        fs::remove_file(zone).unwrap();
+    }
+
+    thread_local! {
+        // What the injected collector returns on each call, and how often it was called.
+        // Each test runs on its own thread.
+        static COLLECTIONS: std::cell::RefCell<(Vec<Vec<InterfaceIp>>, usize)> = Default::default();
+    }
+
+    fn collect_next() -> Vec<InterfaceIp> {
+        COLLECTIONS.with_borrow_mut(|(collections, calls)| {
+            *calls += 1;
+            if collections.is_empty() { Vec::new() } else { collections.remove(0) }
+        })
+    }
+
+    fn cache_collecting(collections: Vec<Vec<InterfaceIp>>) -> IpCache {
+        COLLECTIONS.set((collections, 0));
+        IpCache::new(collect_next)
+    }
+
+    fn collect_calls() -> usize {
+        COLLECTIONS.with_borrow(|(_, calls)| *calls)
+    }
+
+    fn eth0(ip: [u8; 4]) -> InterfaceIp {
+        ("eth0".to_string(), ip.map(|octet| octet.to_string()).join("."), ip)
+    }
+
+    #[test]
+    fn keeps_the_last_addresses_during_the_grace_period() {
+        let settings = IpSettings { grace: Duration::from_secs(60), ..IpSettings::default() };
+        let mut cache = cache_collecting(vec![vec![eth0([192, 168, 1, 42])], vec![], vec![eth0([192, 168, 1, 43])]]);
+
+        assert_eq!(cache.current(&settings), Some(eth0([192, 168, 1, 42])));
+        // A DHCP renewal: nothing is found, but the address is still shown
+        cache.force_refresh();
+        assert_eq!(cache.current(&settings), Some(eth0([192, 168, 1, 42])));
+        assert_eq!(collect_calls(), 2);
+        // ... without running `ip addr` again on every lookup
+        for _ in 0..5 {
+            assert_eq!(cache.current(&settings), Some(eth0([192, 168, 1, 42])));
+            cache.refresh_if_stale(&settings);
+        }
+        assert_eq!(collect_calls(), 2);
+        // The new address shows up with the next forced refresh
+        cache.force_refresh();
+        assert_eq!(cache.current(&settings), Some(eth0([192, 168, 1, 43])));
+        assert_eq!(collect_calls(), 3);
+    }
+
+    #[test]
+    fn without_a_grace_period_addresses_go_at_once() {
+        let settings = IpSettings { grace: Duration::ZERO, ..IpSettings::default() };
+        let mut cache = cache_collecting(vec![vec![eth0([192, 168, 1, 42])], vec![]]);
+
+        assert_eq!(cache.current(&settings), Some(eth0([192, 168, 1, 42])));
+        cache.force_refresh();
+        assert_eq!(cache.current(&settings), None);
+        assert!(cache.addresses.is_empty());
+    }
+
+    // Move every timestamp of the cache `by` into the past, instead of sleeping. Kept to
+    // seconds, as Instant can't go back before the system booted.
+    fn age(cache: &mut IpCache, by: Duration) {
+        let seen = cache.last_good.as_mut().map(|(_, seen)| seen);
+        for t in [cache.last_refresh.as_mut(), cache.last_attempt.as_mut(), seen].into_iter().flatten() {
+            *t -= by;
+        }
+    }
+
+    #[test]
+    fn stale_addresses_fall_back_after_the_grace_period() {
+        let settings = IpSettings { refresh_interval: Duration::from_secs(10), grace: Duration::from_secs(5), ..IpSettings::default() };
+        let mut cache = cache_collecting(vec![vec![eth0([192, 168, 1, 42])], vec![], vec![]]);
+
+        assert_eq!(cache.current(&settings), Some(eth0([192, 168, 1, 42])));
+        // Still fresh, so not re-read
+        age(&mut cache, settings.refresh_interval / 2);
+        assert!(!cache.refresh_if_stale(&settings));
+        assert_eq!(collect_calls(), 1);
+
+        // Stale, and the interface lost its address: the last one was seen longer ago
+        // than the grace period, so it is dropped
+        age(&mut cache, settings.refresh_interval);
+        assert!(cache.refresh_if_stale(&settings));
+        assert_eq!(collect_calls(), 2);
+        assert_eq!(cache.current(&settings), None);
+    }
+
+    #[test]
+    fn stale_addresses_are_kept_within_the_grace_period() {
+        let settings = IpSettings { refresh_interval: Duration::from_secs(10), grace: Duration::from_secs(30), ..IpSettings::default() };
+        let mut cache = cache_collecting(vec![vec![eth0([192, 168, 1, 42])], vec![], vec![]]);
+
+        assert_eq!(cache.current(&settings), Some(eth0([192, 168, 1, 42])));
+        age(&mut cache, Duration::from_secs(10));
+        assert!(!cache.refresh_if_stale(&settings));
+        assert_eq!(collect_calls(), 2);
+        assert_eq!(cache.current(&settings), Some(eth0([192, 168, 1, 42])));
+
+        // Retried once the retry interval has passed, and given up after the grace period
+        age(&mut cache, settings.grace);
+        assert!(cache.refresh_if_stale(&settings));
+        assert_eq!(collect_calls(), 3);
+        assert_eq!(cache.current(&settings), None);
+    }
+
+    #[test]
+    fn byte_usage_in_percent() {
+        assert_eq!(ByteUsage { used: 0, total: 1000 }.percent(), 0.0);
+        assert_eq!(ByteUsage { used: 1_500_000_000, total: 4_000_000_000 }.percent(), 37.5);
+        assert_eq!(ByteUsage { used: 1000, total: 1000 }.percent(), 100.0);
+    }
+
+    #[test]
+    fn disk_usage_comes_from_the_root_mount() {
+        let mounts = |paths: &[&'static str]| paths.iter().map(|path| Path::new(*path)).collect::<Vec<_>>();
+        assert_eq!(root_disk_index(mounts(&["/boot/firmware", "/", "/mnt/usb"])), 1);
+        assert_eq!(root_disk_index(mounts(&["/", "/boot/firmware"])), 0);
+        // Without a root mount, e.g. in a container, the first disk is used
+        assert_eq!(root_disk_index(mounts(&["/data", "/mnt/usb"])), 0);
+        assert_eq!(root_disk_index(mounts(&[])), 0);
+    }
+
+    #[test]
+    fn unavailable_disk_space_counts_as_used() {
+        assert_eq!(disk_space_usage(32_000, 12_000), Some(ByteUsage { used: 20_000, total: 32_000 }));
+        assert_eq!(disk_space_usage(32_000, 32_000), Some(ByteUsage { used: 0, total: 32_000 }));
+        // Reported as more than the total on some filesystems
+        assert_eq!(disk_space_usage(32_000, 40_000), Some(ByteUsage { used: 0, total: 32_000 }));
+        assert_eq!(disk_space_usage(0, 0), None);
+    }
+}
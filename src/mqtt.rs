@@ -0,0 +1,87 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use clap::Args;
+use log::{debug, info, warn};
+use rumqttc::{Client, Event, MqttOptions, Packet, QoS};
+
+#[derive(Args, Debug)]
+pub struct MqttArgs {
+    /// MQTT broker host; publishing is disabled when not set
+    #[arg(long)]
+    pub mqtt_host: Option<String>,
+
+    #[arg(long, default_value_t = 1883)]
+    pub mqtt_port: u16,
+
+    #[arg(long)]
+    pub mqtt_username: Option<String>,
+
+    #[arg(long)]
+    pub mqtt_password: Option<String>,
+
+    /// Base topic, metrics are published to <base>/<metric>
+    #[arg(long, default_value = "rustberry-poe-monitor")]
+    pub mqtt_topic: String,
+}
+
+pub struct MqttPublisher {
+    client: Client,
+    base_topic: String,
+    // Set from the first failed publish until one succeeds again, so an outage is only
+    // warned about once instead of for every metric on every loop
+    failing: AtomicBool,
+}
+
+impl MqttPublisher {
+    // Returns None when no broker is configured
+    pub fn new(args: &MqttArgs) -> Option<Self> {
+        let host = args.mqtt_host.as_ref()?;
+
+        let client_id = format!("rustberry-poe-monitor-{}", std::process::id());
+        let mut options = MqttOptions::new(client_id, host, args.mqtt_port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if let Some(username) = &args.mqtt_username {
+            options.set_credentials(username, args.mqtt_password.clone().unwrap_or_default());
+        }
+
+        let (client, mut connection) = Client::new(options, 32);
+
+        // The connection has to be polled for anything to be sent. It reconnects on its own,
+        // so errors are only logged and retried after a pause.
+        thread::spawn(move || {
+            for event in connection.iter() {
+                match event {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => info!("Connected to MQTT broker"),
+                    Ok(_) => {},
+                    Err(e) => {
+                        warn!("MQTT connection error: {}", e);
+                        thread::sleep(Duration::from_secs(5));
+                    }
+                }
+            }
+        });
+
+        info!("Publishing metrics to MQTT broker {}:{} under {}", host, args.mqtt_port, args.mqtt_topic);
+        Some(MqttPublisher { client, base_topic: args.mqtt_topic.trim_end_matches('/').to_string(), failing: AtomicBool::new(false) })
+    }
+
+    // Queue each metric for publishing without blocking the main loop
+    pub fn publish_metrics(&self, metrics: &[(&str, &str)]) {
+        for (name, value) in metrics {
+            let topic = format!("{}/{}", self.base_topic, name);
+            debug!("Publishing {} = {}", topic, value);
+            match self.client.try_publish(topic, QoS::AtMostOnce, false, value.as_bytes()) {
+                Ok(()) => {
+                    if self.failing.swap(false, Ordering::Relaxed) {
+                        info!("Publishing to MQTT again");
+                    }
+                },
+                Err(e) if !self.failing.swap(true, Ordering::Relaxed) => {
+                    warn!("Failed to publish {} to MQTT: {}. Further failures are only logged at debug level until it works again", name, e);
+                },
+                Err(e) => debug!("Failed to publish {} to MQTT: {}", name, e),
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::{debug, info, warn};
+use rustberry_poe_monitor::format::{convert_temp, TempUnit};
+use rustberry_poe_monitor::metrics::{ByteUsage, InterfaceIp};
+use serde::{Deserialize, Serialize};
+
+use crate::display::DisplayValues;
+
+// Older readings are not worth showing, e.g. after the Pi was switched off for a while
+const MAX_STATE_AGE: Duration = Duration::from_secs(600);
+
+// The last rendered readings, saved on shutdown so the display has something to show right
+// after a restart, until fresh readings are in
+#[derive(Serialize, Deserialize)]
+pub struct SavedState {
+    // Unix timestamp of when the state was saved
+    saved_at: u64,
+    interfaces: Vec<InterfaceIp>,
+    cpu_usage: f32,
+    // Degrees Celsius, so a changed --temp-unit still converts correctly
+    cpu_temp: Option<f32>,
+    ram_usage: Option<f64>,
+    disk_usage: Option<f64>,
+    ram_bytes: Option<ByteUsage>,
+    disk_bytes: Option<ByteUsage>,
+    throttled: Option<String>,
+    poe: Option<String>,
+    fan_running: bool,
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    hat_product: Option<String>,
+    #[serde(default)]
+    hat_vendor: Option<String>,
+}
+
+impl SavedState {
+    pub fn new(values: &DisplayValues, cpu_temp: Option<f32>) -> Self {
+        SavedState {
+            saved_at: unix_time(),
+            interfaces: values.interfaces.to_vec(),
+            cpu_usage: values.cpu_usage,
+            cpu_temp,
+            ram_usage: values.ram_usage,
+            disk_usage: values.disk_usage,
+            ram_bytes: values.ram_bytes,
+            disk_bytes: values.disk_bytes,
+            throttled: values.throttled.map(str::to_string),
+            poe: values.poe.map(str::to_string),
+            fan_running: values.fan_running,
+            model: values.model.map(str::to_string),
+            hat_product: values.hat_product.map(str::to_string),
+            hat_vendor: values.hat_vendor.map(str::to_string),
+        }
+    }
+
+    // Read a saved state. A missing, unreadable or outdated file gives None, so the display
+    // starts blank as usual.
+    pub fn load(path: &str) -> Option<Self> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                debug!("No saved state in {}: {}", path, e);
+                return None;
+            }
+        };
+        let state: SavedState = match serde_json::from_str(&content) {
+            Ok(state) => state,
+            Err(e) => {
+                warn!("Ignoring corrupt state file {}: {}", path, e);
+                return None;
+            }
+        };
+
+        let age = Duration::from_secs(unix_time().saturating_sub(state.saved_at));
+        if age > MAX_STATE_AGE {
+            info!("Ignoring saved state from {:?} ago", age);
+            return None;
+        }
+        Some(state)
+    }
+
+    // Write to a temporary file first, so a crash while saving can't leave a truncated state
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let temp_path = format!("{}.tmp", path);
+        fs::write(&temp_path, serde_json::to_string(self)?)?;
+        fs::rename(&temp_path, path)
+    }
+
+    pub fn display_values(&self, temp_unit: TempUnit) -> DisplayValues<'_> {
+        DisplayValues {
+            interfaces: &self.interfaces,
+            cpu_usage: self.cpu_usage,
+            cpu_temp: self.cpu_temp.map(|temp| convert_temp(temp, temp_unit)),
+            ram_usage: self.ram_usage,
+            disk_usage: self.disk_usage,
+            ram_bytes: self.ram_bytes,
+            disk_bytes: self.disk_bytes,
+            processes: &[],
+            throttled: self.throttled.as_deref(),
+            poe: self.poe.as_deref(),
+            fan_running: self.fan_running,
+            model: self.model.as_deref(),
+            hat_product: self.hat_product.as_deref(),
+            hat_vendor: self.hat_vendor.as_deref(),
+            // Counters from before the restart are meaningless
+            net_errors: None,
+        }
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
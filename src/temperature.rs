@@ -0,0 +1,48 @@
+use std::collections::VecDeque;
+
+// Moving average over the last `window` temperature readings, so a single noisy sample
+// can't flip the fan or make the displayed value jitter
+pub struct TemperatureAverage {
+    samples: VecDeque<f32>,
+    window: usize,
+}
+
+impl TemperatureAverage {
+    pub fn new(window: usize) -> Self {
+        let window = window.max(1);
+        TemperatureAverage { samples: VecDeque::with_capacity(window), window }
+    }
+
+    // Add a raw reading and return the average of the samples currently in the window
+    pub fn push(&mut self, temp: f32) -> f32 {
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(temp);
+        self.samples.iter().sum::<f32>() / self.samples.len() as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn averages_over_a_filling_then_sliding_window() {
+        let mut average = TemperatureAverage::new(3);
+        // Fewer samples than the window: the average of what is there
+        assert_eq!(average.push(40.0), 40.0);
+        assert_eq!(average.push(50.0), 45.0);
+        assert_eq!(average.push(60.0), 50.0);
+        // Full: the oldest sample drops out
+        assert_eq!(average.push(70.0), 60.0);
+        assert_eq!(average.push(70.0), 200.0 / 3.0);
+    }
+
+    #[test]
+    fn window_of_zero_keeps_the_last_sample() {
+        let mut average = TemperatureAverage::new(0);
+        assert_eq!(average.push(40.0), 40.0);
+        assert_eq!(average.push(60.0), 60.0);
+    }
+}
@@ -0,0 +1,97 @@
+use std::process::Command;
+use log::{debug, warn};
+
+// Bits of the `vcgencmd get_throttled` mask that describe the current state; the same
+// conditions shifted by 16 mean "has occurred since boot" and are not shown
+const UNDER_VOLTAGE: u32 = 1 << 0;
+const FREQUENCY_CAPPED: u32 = 1 << 1;
+const THROTTLED: u32 = 1 << 2;
+const SOFT_TEMP_LIMIT: u32 = 1 << 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ThrottleStatus {
+    Ok,
+    UnderVoltage,
+    Throttled,
+    // vcgencmd is missing or its output couldn't be parsed
+    Unknown,
+}
+
+impl ThrottleStatus {
+    // Under-voltage is reported first since it is usually the cause of the throttling
+    pub fn from_bitmask(mask: u32) -> Self {
+        if mask & UNDER_VOLTAGE != 0 {
+            ThrottleStatus::UnderVoltage
+        } else if mask & (FREQUENCY_CAPPED | THROTTLED | SOFT_TEMP_LIMIT) != 0 {
+            ThrottleStatus::Throttled
+        } else {
+            ThrottleStatus::Ok
+        }
+    }
+
+    // Short indicator for the display
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ThrottleStatus::Ok => "OK",
+            ThrottleStatus::UnderVoltage => "UV",
+            ThrottleStatus::Throttled => "THR",
+            ThrottleStatus::Unknown => "N/A",
+        }
+    }
+}
+
+// Parse output such as "throttled=0x50005"
+fn parse_throttled(output: &str) -> Option<u32> {
+    let value = output.trim().strip_prefix("throttled=")?;
+    u32::from_str_radix(value.trim_start_matches("0x"), 16).ok()
+}
+
+// Ask the firmware for the current throttling state
+pub fn get_throttle_status() -> ThrottleStatus {
+    match Command::new("vcgencmd").arg("get_throttled").output() {
+        Ok(output) => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            match parse_throttled(&stdout) {
+                Some(mask) => {
+                    debug!("Throttle mask: {:#x}", mask);
+                    ThrottleStatus::from_bitmask(mask)
+                },
+                None => {
+                    warn!("Unexpected vcgencmd output: {}", stdout.trim());
+                    ThrottleStatus::Unknown
+                }
+            }
+        },
+        Err(e) => {
+            debug!("Failed to run vcgencmd: {}", e);
+            ThrottleStatus::Unknown
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_from_bitmask() {
+        assert_eq!(ThrottleStatus::from_bitmask(0x0), ThrottleStatus::Ok);
+        // Under-voltage and throttled now, both also since boot
+        assert_eq!(ThrottleStatus::from_bitmask(0x50005), ThrottleStatus::UnderVoltage);
+        // Soft temperature limit now and since boot
+        assert_eq!(ThrottleStatus::from_bitmask(0x80008), ThrottleStatus::Throttled);
+        assert_eq!(ThrottleStatus::from_bitmask(0x2), ThrottleStatus::Throttled);
+        // Only past conditions count as OK
+        assert_eq!(ThrottleStatus::from_bitmask(0x50000), ThrottleStatus::Ok);
+    }
+
+    #[test]
+    fn parses_vcgencmd_output() {
+        assert_eq!(parse_throttled("throttled=0x0\n"), Some(0x0));
+        assert_eq!(parse_throttled("throttled=0x50005\n"), Some(0x50005));
+        assert_eq!(parse_throttled("throttled=0x80008"), Some(0x80008));
+        assert_eq!(parse_throttled("VCHI initialization failed\n"), None);
+        assert_eq!(parse_throttled("throttled=zz"), None);
+        assert_eq!(parse_throttled(""), None);
+    }
+}
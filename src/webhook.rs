@@ -0,0 +1,54 @@
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+
+const MAX_ATTEMPTS: u32 = 3;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Webhook {
+    url: String,
+}
+
+impl Webhook {
+    pub fn new(url: &str) -> Self {
+        info!("Sending events to webhook: {}", url);
+        Webhook { url: url.to_string() }
+    }
+
+    // POST {"event": ..., "timestamp": ..., <fields>} on a background thread so a slow
+    // endpoint can't stall the main loop. Failed requests are retried a few times with backoff.
+    pub fn send_event(&self, event: &str, fields: Value) {
+        let mut body = json!({
+            "event": event,
+            "timestamp": SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        });
+        if let (Some(body), Value::Object(fields)) = (body.as_object_mut(), fields) {
+            body.extend(fields);
+        }
+
+        let url = self.url.clone();
+        let body = body.to_string();
+        thread::spawn(move || {
+            for attempt in 1..=MAX_ATTEMPTS {
+                let result = ureq::post(&url)
+                    .timeout(REQUEST_TIMEOUT)
+                    .set("Content-Type", "application/json")
+                    .send_string(&body);
+                match result {
+                    Ok(_) => {
+                        debug!("Webhook delivered: {}", body);
+                        return;
+                    },
+                    Err(e) => {
+                        warn!("Webhook attempt {}/{} failed: {}", attempt, MAX_ATTEMPTS, e);
+                        if attempt < MAX_ATTEMPTS {
+                            thread::sleep(Duration::from_secs(2u64.pow(attempt)));
+                        }
+                    }
+                }
+            }
+            warn!("Giving up on webhook event: {}", body);
+        });
+    }
+}
@@ -0,0 +1,49 @@
+// Every bundled layout must load and fit on the panel: render each one with the fixed
+// sample readings on both panel sizes and check that nothing is drawn past the edges. The
+// bundled layouts label the temperature in Celsius, so that is the unit they are drawn in.
+use std::path::{Path, PathBuf};
+
+use rustberry_poe_monitor::display::{render_sample, PoeDisplay};
+use rustberry_poe_monitor::display_types::PanelSize;
+use rustberry_poe_monitor::format::TempUnit;
+
+// Not a layout, but the older file with a layout per orientation
+const NOT_LAYOUTS: &[&str] = &["rustberry-poe-monitor.json"];
+
+fn bundled_layouts() -> Vec<PathBuf> {
+    // The formats this build can read
+    let mut extensions = vec!["json"];
+    if cfg!(feature = "yaml") {
+        extensions.push("yaml");
+    }
+    if cfg!(feature = "toml") {
+        extensions.push("toml");
+    }
+
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("etc_rustberry-poe-monitor");
+    let mut layouts: Vec<PathBuf> = std::fs::read_dir(dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| !NOT_LAYOUTS.contains(&path.file_name().unwrap().to_str().unwrap()))
+        .filter(|path| path.extension().and_then(|e| e.to_str()).is_some_and(|e| extensions.contains(&e)))
+        .collect();
+    layouts.sort();
+    layouts
+}
+
+#[test]
+fn bundled_layouts_fit_on_the_panel() {
+    let layouts = bundled_layouts();
+    assert!(layouts.iter().any(|path| path.ends_with("landscape.json")));
+    assert!(layouts.iter().any(|path| path.ends_with("portrait.json")));
+
+    for path in layouts {
+        for panel_size in [PanelSize::Size128x32, PanelSize::Size128x64] {
+            let mut config = PoeDisplay::load_config_from_file(path.to_str().unwrap())
+                .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+            let frame = render_sample(&mut config, TempUnit::Celsius, panel_size)
+                .unwrap_or_else(|e| panic!("{} on {:?}: {:?}", path.display(), panel_size, e));
+            assert_eq!(frame.clipped(), 0, "{} on {:?} is drawn past the edges:\n{}",
+                       path.display(), panel_size, frame.to_text());
+        }
+    }
+}
@@ -0,0 +1,37 @@
+// Render the bundled layouts with the fixed sample readings and compare them with the
+// snapshots in docs/snapshots. After an intended change, regenerate them with
+// `sh docs/snapshots/update.sh` and review the diff.
+use std::path::Path;
+
+use rustberry_poe_monitor::default_config::get_default_display_config;
+use rustberry_poe_monitor::display::{render_sample, PoeDisplay};
+use rustberry_poe_monitor::display_types::{DisplayConfig, PanelSize};
+use rustberry_poe_monitor::format::TempUnit;
+
+fn assert_matches_snapshot(mut config: DisplayConfig, snapshot: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("docs/snapshots").join(snapshot);
+    let expected = std::fs::read_to_string(&path).unwrap();
+    let frame = render_sample(&mut config, TempUnit::Celsius, PanelSize::Size128x32).unwrap();
+    assert!(frame.to_text() == expected,
+            "{} differs from the rendered frame:\n{}", path.display(), frame.to_text());
+}
+
+fn bundled(layout: &str) -> DisplayConfig {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("etc_rustberry-poe-monitor").join(layout);
+    PoeDisplay::load_config_from_file(path.to_str().unwrap()).unwrap()
+}
+
+#[test]
+fn landscape_layout() {
+    assert_matches_snapshot(bundled("landscape.json"), "landscape.txt");
+}
+
+#[test]
+fn portrait_layout() {
+    assert_matches_snapshot(bundled("portrait.json"), "portrait.txt");
+}
+
+#[test]
+fn default_layout() {
+    assert_matches_snapshot(get_default_display_config(), "default.txt");
+}